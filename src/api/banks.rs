@@ -0,0 +1,97 @@
+use crate::router::router_handle::RouterHandle;
+use actix_web::{HttpResponse, Responder, get, put, web};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NoteUpdate {
+    state: Option<bool>,
+    color: Option<ColorRef>,
+}
+
+/// Selects the controller by its position in the config, the first one by default
+#[derive(Debug, Deserialize)]
+pub(crate) struct ControllerQuery {
+    #[serde(default)]
+    controller: usize,
+}
+
+/// Color as velocity or as name from the controller profile
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ColorRef {
+    Number(u8),
+    Name(String),
+}
+
+#[get("/banks/current")]
+pub(crate) async fn get_current_bank(
+    router: web::Data<RouterHandle>,
+    query: web::Query<ControllerQuery>,
+) -> impl Responder {
+    info!("Get current bank endpoint called");
+
+    match router.get_current_bank(query.controller) {
+        Ok(bank) => HttpResponse::Ok().json(json!({ "bank": bank })),
+        Err(err) => {
+            warn!("Get current bank endpoint failed: {}", err);
+            HttpResponse::NotFound().json(json!({ "error": err.to_string() }))
+        }
+    }
+}
+
+#[get("/banks/{bank}/notes")]
+pub(crate) async fn get_bank_notes(
+    router: web::Data<RouterHandle>,
+    path: web::Path<u8>,
+    query: web::Query<ControllerQuery>,
+) -> impl Responder {
+    let bank = path.into_inner();
+    info!("Get notes endpoint called (bank {})", bank);
+
+    match router.get_bank_notes(query.controller, bank) {
+        Ok(notes) => HttpResponse::Ok().json(notes),
+        Err(err) => {
+            warn!("Get notes endpoint failed: {}", err);
+            HttpResponse::NotFound().json(json!({ "error": err.to_string() }))
+        }
+    }
+}
+
+#[put("/banks/{bank}/notes/{note}")]
+pub(crate) async fn set_bank_note(
+    router: web::Data<RouterHandle>,
+    path: web::Path<(u8, u8)>,
+    query: web::Query<ControllerQuery>,
+    body: web::Json<NoteUpdate>,
+) -> impl Responder {
+    let (bank, note) = path.into_inner();
+    info!("Set note endpoint called (bank {}, note {})", bank, note);
+
+    let color = match &body.color {
+        Some(ColorRef::Number(color)) => Some(*color),
+        Some(ColorRef::Name(name)) => match router
+            .get_router_config()
+            .controllers
+            .get(query.controller)
+            .and_then(|controller| controller.colors.get(name))
+        {
+            Some(color) => Some(*color),
+            None => {
+                warn!("Set note endpoint failed: Unknown color '{}'", name);
+                return HttpResponse::BadRequest()
+                    .json(json!({ "error": format!("Unknown color '{}'", name) }));
+            }
+        },
+        None => None,
+    };
+
+    match router.set_note(query.controller, bank, note, body.state, color) {
+        Ok(note) => HttpResponse::Ok().json(note),
+        Err(err) => {
+            warn!("Set note endpoint failed: {}", err);
+            HttpResponse::BadRequest().json(json!({ "error": err.to_string() }))
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use crate::router::router_handle::RouterHandle;
+use actix_web::{HttpResponse, Responder, post, web};
+use log::{info, warn};
+use serde_json::json;
+
+#[post("/config/reload")]
+pub(crate) async fn reload_config(router: web::Data<RouterHandle>) -> impl Responder {
+    info!("Reload config endpoint called");
+
+    match router.reload_config() {
+        Ok(_) => HttpResponse::Ok().json(json!({ "message": "Config reloaded" })),
+        Err(err) => {
+            warn!("Reload config endpoint failed: {}", err);
+            HttpResponse::BadRequest().json(json!({ "error": err.to_string() }))
+        }
+    }
+}
@@ -1 +1,5 @@
+pub(crate) mod banks;
+pub(crate) mod config;
+pub(crate) mod ports;
+pub(crate) mod status;
 pub(crate) mod test;
@@ -0,0 +1,17 @@
+use crate::router::{midi_connection::MidiRouter, router_handle::RouterHandle};
+use actix_web::{HttpResponse, Responder, get, web};
+use log::{info, warn};
+use serde_json::json;
+
+#[get("/ports")]
+pub(crate) async fn get_ports(router: web::Data<RouterHandle>) -> impl Responder {
+    info!("Get ports endpoint called");
+
+    match MidiRouter::list_ports(Some(&router.get_router_config())) {
+        Ok(ports) => HttpResponse::Ok().json(ports),
+        Err(err) => {
+            warn!("Get ports endpoint failed: {}", err);
+            HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    }
+}
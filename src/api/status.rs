@@ -0,0 +1,32 @@
+use crate::router::router_handle::{RouterHandle, RouterStatus};
+use actix_web::{HttpResponse, Responder, get, web};
+use log::info;
+use serde_json::json;
+
+#[get("/status")]
+pub(crate) async fn get_status(router: web::Data<RouterHandle>) -> impl Responder {
+    info!("Status endpoint called");
+
+    let status = match router.get_status() {
+        RouterStatus::Connecting => json!({ "status": "connecting" }),
+        RouterStatus::Connected => json!({ "status": "connected" }),
+        RouterStatus::Waiting {
+            device,
+            attempt,
+            error,
+        } => json!({
+            "status": "waiting",
+            "message": format!("waiting for {}...", device),
+            "attempt": attempt,
+            "max_attempts": router.get_router_config().retry.get_attempts(),
+            "error": error,
+        }),
+        RouterStatus::Disconnected => json!({
+            "status": "disconnected",
+            "message": "waiting for ports...",
+        }),
+        RouterStatus::Failed(error) => json!({ "status": "failed", "error": error }),
+    };
+
+    HttpResponse::Ok().json(status)
+}
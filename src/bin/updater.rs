@@ -18,7 +18,7 @@ async fn main() -> Result<()> {
     let res = client.get(download_url).send().await?.bytes().await?;
 
     let exe_path = "midi-router.exe";
-    fs::write(&exe_path, &res)?;
+    fs::write(exe_path, &res)?;
 
     Command::new(exe_path).spawn()?;
 
@@ -2,20 +2,30 @@ mod api;
 mod router;
 mod utils;
 
-use crate::utils::{
-    config::Config,
-    helper::{ForwardLogger, update},
-    threads::{api_thread, router_thread, tui_thread},
+use crate::{
+    router::{midi_connection::MidiRouter, router_handle::RouterHandle},
+    utils::{
+        cli::Cli,
+        config::Config,
+        helper::{ForwardLogger, update},
+        threads::{ApiStatus, api_thread, router_thread, tui_thread},
+    },
 };
 use anyhow::Result;
+use clap::Parser;
+use env_logger::Target;
 use log::{LevelFilter, info};
-use std::sync::{
-    Arc, Mutex,
-    atomic::AtomicBool,
-    mpsc::{Receiver, channel},
+use std::{
+    fs::File,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, channel},
+    },
 };
 
-fn logging() -> Result<(Receiver<String>, Receiver<String>)> {
+fn logging(level: LevelFilter) -> Result<(Receiver<String>, Receiver<String>)> {
     let (log_tx_router, log_rx_router) = channel::<String>();
     let (log_tx_api, log_rx_api) = channel::<String>();
 
@@ -25,11 +35,60 @@ fn logging() -> Result<(Receiver<String>, Receiver<String>)> {
     };
 
     log::set_boxed_logger(Box::new(forward))?;
-    log::set_max_level(LevelFilter::Info);
+    log::set_max_level(level);
 
     Ok((log_rx_router, log_rx_api))
 }
 
+/// Logs to stdout or a file instead of the TUI panes
+fn headless_logging(level: LevelFilter, log_file: Option<&Path>) -> Result<()> {
+    let target = match log_file {
+        Some(path) => Target::Pipe(Box::new(File::create(path)?)),
+        None => Target::Stdout,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .target(target)
+        .format_timestamp_secs()
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Prints all MIDI ports and the ones the config matches
+fn list_ports(config_path: &Path) -> Result<()> {
+    let config = match Config::new(config_path) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            println!("No matches shown, config not loaded: {}", err);
+            None
+        }
+    };
+
+    let ports = MidiRouter::list_ports(config.as_ref().map(|config| &config.router))?;
+
+    for (title, ports) in [("Inputs", ports.inputs), ("Outputs", ports.outputs)] {
+        println!("{}:", title);
+
+        for port in ports {
+            let roles = port
+                .roles
+                .iter()
+                .map(|role| role.to_string())
+                .collect::<Vec<_>>();
+
+            if roles.is_empty() {
+                println!("  [{}] {}", port.index, port.name);
+            } else {
+                println!("  [{}] {}  <- {}", port.index, port.name, roles.join(", "));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 async fn check_update(config: &Config) -> Result<()> {
     info!("Checking for updates...");
 
@@ -42,37 +101,72 @@ async fn check_update(config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn init_threads(config: &Config, logs: (Receiver<String>, Receiver<String>)) -> Result<()> {
+async fn init_threads(
+    config: &Config,
+    cli: &Cli,
+    logs: Option<(Receiver<String>, Receiver<String>)>,
+) -> Result<()> {
     let restart = Arc::new(AtomicBool::new(false));
     let exit = Arc::new(AtomicBool::new(false));
+    let router = RouterHandle::new(config, cli.config.clone(), restart.clone());
+    router.restore_state();
+
+    router_thread(restart.clone(), exit.clone(), router.clone());
+
+    let api_status = Arc::new(Mutex::new(ApiStatus::Off));
 
-    router_thread(
-        restart.clone(),
-        exit.clone(),
-        config.maps.clone(),
-        config.router.controller_name.clone(),
-        config.router.software_name.clone(),
-    );
-    api_thread(exit.clone(), config.api.clone());
-    tui_thread(
-        restart.clone(),
-        exit.clone(),
-        logs.0,
-        logs.1,
-        config.router.controller_name.clone(),
-        config.router.software_name.clone(),
-    )?;
+    if config.api.enabled && !cli.no_api {
+        api_thread(
+            exit.clone(),
+            config.api.clone(),
+            router.clone(),
+            api_status.clone(),
+        );
+    } else {
+        info!(target: "api", "REST api is disabled");
+    }
+
+    if let Some((log_router, log_api)) = logs {
+        tui_thread(
+            restart.clone(),
+            exit.clone(),
+            log_router,
+            log_api,
+            router.clone(),
+            api_status,
+        )?;
+    } else {
+        info!("Running headless, press Ctrl+C to exit");
+        tokio::signal::ctrl_c().await?;
+        exit.store(true, Ordering::SeqCst);
+        info!("Exiting...");
+    }
+
+    router.save_state();
 
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let logs = logging()?;
-    let config = &Config::new()?;
+    let cli = Cli::parse();
+
+    if cli.list_ports {
+        return list_ports(&cli.config);
+    }
+
+    let logs = if cli.headless {
+        headless_logging(cli.log_level, cli.log_file.as_deref())?;
+        None
+    } else {
+        Some(logging(cli.log_level)?)
+    };
+
+    let mut config = Config::new(&cli.config)?;
+    config.dev |= cli.dev;
 
-    check_update(config).await?;
-    init_threads(config, logs)?;
+    check_update(&config).await?;
+    init_threads(&config, &cli, logs).await?;
 
     Ok(())
 }
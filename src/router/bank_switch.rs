@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use wmidi::{
+    MidiMessage,
+    MidiMessage::{ControlChange, NoteOff, NoteOn, ProgramChange},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BankSwitchConfig {
+    #[serde(default = "default_banks")]
+    banks: u8,
+    #[serde(flatten)]
+    trigger: BankSwitchTrigger,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BankSwitchTrigger {
+    /// The control selects the bank of its MIDI channel (APC40 MK2 track buttons)
+    ControlChange { control: u8 },
+    /// Every note selects one bank, in the order of the list
+    Note { notes: Vec<u8> },
+    /// Two notes step to the next/previous bank
+    Buttons {
+        next: u8,
+        prev: u8,
+        #[serde(default)]
+        wrap: bool,
+    },
+    /// The program number selects the bank
+    ProgramChange,
+}
+
+fn default_banks() -> u8 {
+    9
+}
+
+impl Default for BankSwitchConfig {
+    fn default() -> Self {
+        Self {
+            banks: default_banks(),
+            trigger: BankSwitchTrigger::ControlChange { control: 16 },
+        }
+    }
+}
+
+impl BankSwitchConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=128).contains(&self.banks) {
+            return Err(anyhow!("Bank count {} is out of range (1-128)", self.banks));
+        }
+
+        match &self.trigger {
+            BankSwitchTrigger::ControlChange { control } if *control > 127 => Err(anyhow!(
+                "Bank switch control {} is out of range (0-127)",
+                control
+            )),
+            BankSwitchTrigger::Note { notes } if notes.iter().any(|&note| note > 127) => {
+                Err(anyhow!("Bank switch notes are out of range (0-127)"))
+            }
+            BankSwitchTrigger::Note { notes } if notes.len() > self.banks as usize => Err(anyhow!(
+                "More bank switch notes than banks ({})",
+                self.banks
+            )),
+            BankSwitchTrigger::Buttons { next, prev, .. } if *next > 127 || *prev > 127 => {
+                Err(anyhow!("Bank switch buttons are out of range (0-127)"))
+            }
+            BankSwitchTrigger::Buttons { next, prev, .. } if next == prev => Err(anyhow!(
+                "Bank switch buttons next and prev must be different notes"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_banks(&self) -> u8 {
+        self.banks
+    }
+
+    /// Returns the bank the message switches to, if it is a bank switch
+    pub fn target_bank(&self, msg: &MidiMessage, current_bank: u8) -> Option<u8> {
+        let bank = match (&self.trigger, msg) {
+            (BankSwitchTrigger::ControlChange { control }, ControlChange(channel, function, _))
+                if u8::from(*function) == *control =>
+            {
+                channel.index()
+            }
+            (BankSwitchTrigger::Note { notes }, NoteOn(_, note, _)) => notes
+                .iter()
+                .position(|&bank_note| bank_note == u8::from(*note))?
+                as u8,
+            (BankSwitchTrigger::Buttons { next, prev, wrap }, NoteOn(_, note, _)) => {
+                let last = self.banks - 1;
+
+                match u8::from(*note) {
+                    note if note == *next && current_bank < last => current_bank + 1,
+                    note if note == *next && *wrap => 0,
+                    note if note == *prev && current_bank > 0 => current_bank - 1,
+                    note if note == *prev && *wrap => last,
+                    _ => return None,
+                }
+            }
+            (BankSwitchTrigger::ProgramChange, ProgramChange(_, program)) => u8::from(*program),
+            _ => return None,
+        };
+
+        (bank < self.banks).then_some(bank)
+    }
+
+    /// Notes that switch the bank, they never reach the toggle notes
+    pub fn get_switch_notes(&self) -> Vec<u8> {
+        match &self.trigger {
+            BankSwitchTrigger::Note { notes } => notes.clone(),
+            BankSwitchTrigger::Buttons { next, prev, .. } => vec![*next, *prev],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Note triggers are handled by the router only and never reach the software
+    pub fn is_consumed(&self, msg: &MidiMessage) -> bool {
+        let note = match msg {
+            NoteOn(_, note, _) | NoteOff(_, note, _) => u8::from(*note),
+            _ => return false,
+        };
+
+        match &self.trigger {
+            BankSwitchTrigger::Note { notes } => notes.contains(&note),
+            BankSwitchTrigger::Buttons { next, prev, .. } => note == *next || note == *prev,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How toggles on the controller are reconciled with the feedback of the software
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeedbackConfig {
+    #[serde(default)]
+    mode: FeedbackMode,
+    /// Time the software has to confirm a toggle in confirmed mode
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    /// Repaints all LEDs from the states every n ms, 0 turns it off
+    #[serde(default)]
+    led_repaint_interval_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum FeedbackMode {
+    /// The LED changes right away, feedback from the software overrides it whenever it comes
+    #[default]
+    Optimistic,
+    /// The LED changes right away and is reverted if the software doesn't echo the toggle in time
+    Confirmed,
+}
+
+fn default_timeout_ms() -> u64 {
+    500
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            mode: FeedbackMode::default(),
+            timeout_ms: default_timeout_ms(),
+            led_repaint_interval_ms: 0,
+        }
+    }
+}
+
+impl FeedbackConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.mode == FeedbackMode::Confirmed && self.timeout_ms == 0 {
+            return Err(anyhow!(
+                "Feedback timeout_ms must be at least 1 in confirmed mode"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Toggles waiting for the echo of the software, and the time of the last LED repaint
+pub struct FeedbackTracker {
+    config: FeedbackConfig,
+    /// Previous state of every unconfirmed (bank, note) and when it was toggled
+    pending: HashMap<(u8, u8), (bool, Instant)>,
+    last_repaint: Instant,
+}
+
+impl FeedbackTracker {
+    pub fn new(config: &FeedbackConfig) -> Self {
+        Self {
+            config: config.clone(),
+            pending: HashMap::new(),
+            last_repaint: Instant::now(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: &FeedbackConfig) {
+        if config.mode == FeedbackMode::Optimistic {
+            self.pending.clear();
+        }
+
+        self.config = config.clone();
+    }
+
+    /// Remembers the state before a toggle, so it can be reverted without an echo.
+    /// A note toggled again before the echo keeps its first previous state.
+    pub fn expect(&mut self, bank: u8, note: u8, previous_state: bool) {
+        if self.config.mode == FeedbackMode::Confirmed {
+            self.pending
+                .entry((bank, note))
+                .or_insert((previous_state, Instant::now()));
+        }
+    }
+
+    pub fn confirm(&mut self, bank: u8, note: u8) {
+        self.pending.remove(&(bank, note));
+    }
+
+    /// Bank, note and previous state of all toggles the software didn't confirm in time
+    pub fn take_expired(&mut self) -> Vec<(u8, u8, bool)> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut expired = Vec::new();
+
+        self.pending
+            .retain(|&(bank, note), (previous_state, toggled)| {
+                if toggled.elapsed() < timeout {
+                    return true;
+                }
+
+                expired.push((bank, note, *previous_state));
+                false
+            });
+
+        expired
+    }
+
+    pub fn is_repaint_due(&mut self) -> bool {
+        let interval = Duration::from_millis(self.config.led_repaint_interval_ms);
+
+        if interval.is_zero() || self.last_repaint.elapsed() < interval {
+            return false;
+        }
+
+        self.last_repaint = Instant::now();
+        true
+    }
+}
@@ -1,9 +1,8 @@
-use crate::router::{midi_handler::MidiHandler, output_connection::OutputConnection};
+use crate::router::router_handle::RouterHandle;
 use InputMessage::{ControllerMessage, SoftwareMessage};
 use anyhow::{Error, Result, anyhow};
 use log::error;
 use midir::{MidiInput, MidiInputConnection, MidiInputPort};
-use std::sync::{Arc, Mutex};
 use wmidi::MidiMessage;
 
 pub struct InputConnection {
@@ -11,7 +10,8 @@ pub struct InputConnection {
 }
 
 pub enum InputMessage {
-    ControllerMessage,
+    /// Index of the controller in the config
+    ControllerMessage(usize),
     SoftwareMessage,
 }
 
@@ -25,54 +25,79 @@ impl InputConnection {
         name: &str,
         midi: MidiInput,
         port: &MidiInputPort,
-        handler: Arc<Mutex<MidiHandler>>,
-        to_controller_connection: Arc<Mutex<OutputConnection>>,
-        to_software_connection: Arc<Mutex<OutputConnection>>,
+        router: RouterHandle,
         msg_type: InputMessage,
     ) -> Result<()> {
-        let msg_type = msg_type;
+        let connection = midi
+            .connect(
+                port,
+                name,
+                move |_timestamp, message, _data| handle_message(&router, &msg_type, message),
+                (),
+            )
+            .map_err(|err| anyhow!("{}", err))?;
 
-        let connection = midi.connect(
-            port,
-            name,
-            move |_timestamp, message, _data| {
-                if message.is_empty() {
-                    return;
-                }
+        let self_connection = &mut self.connection;
+        *self_connection = Some(connection);
 
-                if let Err(err) = (|| {
-                    let midi_msg = MidiMessage::try_from(message)
-                        .map_err(|err| anyhow!("Failed to parse MIDI message: {}", err))?;
+        Ok(())
+    }
 
-                    match msg_type {
-                        ControllerMessage => {
-                            let mut handler_lock = handler.lock().unwrap();
-                            let mut controller_lock = to_controller_connection.lock().unwrap();
-                            let mut software_lock = to_software_connection.lock().unwrap();
-                            handler_lock.handle_controller_msg(
-                                midi_msg,
-                                &mut controller_lock,
-                                &mut software_lock,
-                            )?;
-                        }
-                        SoftwareMessage => {
-                            let mut handler_lock = handler.lock().unwrap();
-                            let mut controller_lock = to_controller_connection.lock().unwrap();
-                            handler_lock.handle_software_msg(midi_msg, &mut controller_lock)?;
-                        }
-                    }
+    /// Creates an own virtual port other applications can connect to
+    #[cfg(unix)]
+    pub fn create_virtual(
+        &mut self,
+        name: &str,
+        midi: MidiInput,
+        router: RouterHandle,
+        msg_type: InputMessage,
+    ) -> Result<()> {
+        use midir::os::unix::VirtualInput;
 
-                    Ok::<(), Error>(())
-                })() {
-                    error!("{}", err);
-                }
-            },
-            (),
-        )?;
+        let connection = midi
+            .create_virtual(
+                name,
+                move |_timestamp, message, _data| handle_message(&router, &msg_type, message),
+                (),
+            )
+            .map_err(|err| anyhow!("{}", err))?;
 
         let self_connection = &mut self.connection;
         *self_connection = Some(connection);
 
         Ok(())
     }
+
+    #[cfg(not(unix))]
+    pub fn create_virtual(
+        &mut self,
+        _name: &str,
+        _midi: MidiInput,
+        _router: RouterHandle,
+        _msg_type: InputMessage,
+    ) -> Result<()> {
+        Err(anyhow!(
+            "Virtual MIDI ports are not supported on this platform"
+        ))
+    }
+}
+
+fn handle_message(router: &RouterHandle, msg_type: &InputMessage, message: &[u8]) {
+    if message.is_empty() {
+        return;
+    }
+
+    if let Err(err) = (|| {
+        let midi_msg = MidiMessage::try_from(message)
+            .map_err(|err| anyhow!("Failed to parse MIDI message: {}", err))?;
+
+        match msg_type {
+            ControllerMessage(index) => router.handle_controller_msg(*index, midi_msg)?,
+            SoftwareMessage => router.handle_software_msg(midi_msg)?,
+        }
+
+        Ok::<(), Error>(())
+    })() {
+        error!("{}", err);
+    }
 }
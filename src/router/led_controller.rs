@@ -1,20 +1,32 @@
-use crate::router::{output_connection::OutputConnection, state_manager::StateManager};
+use crate::router::{
+    led_profile::{LedConfig, LedProfile},
+    mapping_config::MappingConfig,
+    output_connection::OutputConnection,
+    state_manager::StateManager,
+};
 use anyhow::Result;
-use std::{thread, time::Duration};
-use wmidi::{Channel, MidiMessage, MidiMessage::NoteOn, Note, Velocity};
+use std::{collections::HashMap, thread, time::Duration};
+use wmidi::{Channel, Note};
 
-pub struct LedController;
+pub struct LedController {
+    profile: Box<dyn LedProfile>,
+    /// Pad and channel of every remapped note, their LEDs are painted there
+    controller_notes: HashMap<u8, (Channel, Note)>,
+}
 
 impl LedController {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: &LedConfig, mapping_config: &MappingConfig) -> Self {
+        Self {
+            profile: config.build(),
+            controller_notes: mapping_config.get_controller_notes(),
+        }
     }
 
     pub fn refresh_all_leds(
         &self,
         to_controller_connection: &mut OutputConnection,
         state_manager: &mut StateManager,
-        bank: &Channel,
+        bank: u8,
         toggle_notes: &[u8],
     ) -> Result<()> {
         for &note_u8 in toggle_notes {
@@ -29,39 +41,23 @@ impl LedController {
         &self,
         to_controller_connection: &mut OutputConnection,
         state_manager: &mut StateManager,
-        bank: &Channel,
+        bank: u8,
         note: Note,
     ) -> Result<()> {
         let (state, color) = state_manager.get_note_state_and_color(bank, note)?;
+        let controller_note = self.controller_notes.get(&u8::from(note));
 
-        let velocity = Velocity::from_u8_lossy(*color);
-
-        let message = if *state {
-            NoteOn(Channel::Ch13, note, velocity) // When Note is ON, use Channel 13 for LED ON (Blinking)
-        } else {
-            NoteOn(Channel::Ch1, note, velocity)
+        let (channel, pad) = match controller_note {
+            Some((channel, pad)) => (Some(*channel), *pad),
+            None => (None, note),
         };
 
-        self.send_led_message(to_controller_connection, message)?;
+        for message in self.profile.led_messages(pad, channel, *state, *color) {
+            to_controller_connection.send(&message)?;
+        }
 
         thread::sleep(Duration::from_micros(25)); // Small delay to ensure the port is not overwhelmed
 
         Ok(())
     }
-
-    fn send_led_message(
-        &self,
-        output_connection: &mut OutputConnection,
-        msg: MidiMessage,
-    ) -> Result<()> {
-        let connection = &mut output_connection.connection;
-
-        if let Some(conn) = connection {
-            let mut buffer = [0_u8; 3];
-            let length = msg.copy_to_slice(&mut buffer)?;
-            conn.send(&buffer[..length])?;
-        }
-
-        Ok(())
-    }
 }
@@ -0,0 +1,192 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use wmidi::{Channel, MidiMessage, MidiMessage::NoteOn, Note, Velocity};
+
+/// Turns the state and color of a note into the MIDI messages that light its LED
+pub trait LedProfile: Send {
+    /// The channel is set for remapped pads, which are told apart by the channel they send on
+    fn led_messages(
+        &self,
+        note: Note,
+        channel: Option<Channel>,
+        state: bool,
+        color: u8,
+    ) -> Vec<MidiMessage<'static>>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "profile", rename_all = "snake_case")]
+pub enum LedConfig {
+    /// The MIDI channel selects solid, one shot, pulsing or blinking, the velocity is the color
+    Apc40Mk2 {
+        #[serde(default = "default_apc40_on")]
+        on: Apc40LedMode,
+        #[serde(default)]
+        off: Apc40LedMode,
+    },
+    /// Fixed velocities for on and off, for controllers with single color LEDs
+    Velocity {
+        #[serde(default = "default_channel")]
+        channel: u8,
+        #[serde(default = "default_on_velocity")]
+        on_velocity: u8,
+        #[serde(default)]
+        off_velocity: u8,
+        /// Use the color from the software instead of on_velocity
+        #[serde(default)]
+        use_color: bool,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Apc40LedMode {
+    #[serde(default)]
+    mode: Apc40LedType,
+    /// 0 = 1/24, 1 = 1/16, 2 = 1/8, 3 = 1/4, 4 = 1/2
+    #[serde(default)]
+    speed: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum Apc40LedType {
+    #[default]
+    Solid,
+    OneShot,
+    Pulse,
+    Blink,
+}
+
+fn default_apc40_on() -> Apc40LedMode {
+    Apc40LedMode {
+        mode: Apc40LedType::Blink,
+        speed: 1,
+    }
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_on_velocity() -> u8 {
+    127
+}
+
+impl Default for LedConfig {
+    fn default() -> Self {
+        LedConfig::Apc40Mk2 {
+            on: default_apc40_on(),
+            off: Apc40LedMode::default(),
+        }
+    }
+}
+
+impl LedConfig {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            LedConfig::Apc40Mk2 { on, off } => {
+                if on.speed > 4 || off.speed > 4 {
+                    return Err(anyhow!("LED speed is out of range (0-4)"));
+                }
+            }
+            LedConfig::Velocity {
+                channel,
+                on_velocity,
+                off_velocity,
+                ..
+            } => {
+                if !(1..=16).contains(channel) {
+                    return Err(anyhow!("LED channel {} is out of range (1-16)", channel));
+                }
+
+                if *on_velocity > 127 || *off_velocity > 127 {
+                    return Err(anyhow!("LED velocities are out of range (0-127)"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self) -> Box<dyn LedProfile> {
+        match self.clone() {
+            LedConfig::Apc40Mk2 { on, off } => Box::new(Apc40Mk2Profile { on, off }),
+            LedConfig::Velocity {
+                channel,
+                on_velocity,
+                off_velocity,
+                use_color,
+            } => Box::new(VelocityProfile {
+                channel: Channel::from_index(channel - 1).unwrap_or(Channel::Ch1),
+                on_velocity: Velocity::from_u8_lossy(on_velocity),
+                off_velocity: Velocity::from_u8_lossy(off_velocity),
+                use_color,
+            }),
+        }
+    }
+}
+
+pub struct Apc40Mk2Profile {
+    on: Apc40LedMode,
+    off: Apc40LedMode,
+}
+
+impl Apc40LedMode {
+    fn channel(&self) -> Channel {
+        // Ch1 is solid, one shot starts at Ch2, pulsing at Ch7 and blinking at Ch12
+        let index = match self.mode {
+            Apc40LedType::Solid => 0,
+            Apc40LedType::OneShot => 1 + self.speed,
+            Apc40LedType::Pulse => 6 + self.speed,
+            Apc40LedType::Blink => 11 + self.speed,
+        };
+
+        Channel::from_index(index).unwrap_or(Channel::Ch1)
+    }
+}
+
+impl LedProfile for Apc40Mk2Profile {
+    fn led_messages(
+        &self,
+        note: Note,
+        channel: Option<Channel>,
+        state: bool,
+        color: u8,
+    ) -> Vec<MidiMessage<'static>> {
+        // The channel of a remapped pad can't select the mode, so it is solid on or off
+        if let Some(channel) = channel {
+            let color = if state { color } else { 0 };
+            return vec![NoteOn(channel, note, Velocity::from_u8_lossy(color))];
+        }
+
+        let mode = if state { &self.on } else { &self.off };
+
+        vec![NoteOn(mode.channel(), note, Velocity::from_u8_lossy(color))]
+    }
+}
+
+pub struct VelocityProfile {
+    channel: Channel,
+    on_velocity: Velocity,
+    off_velocity: Velocity,
+    use_color: bool,
+}
+
+impl LedProfile for VelocityProfile {
+    fn led_messages(
+        &self,
+        note: Note,
+        channel: Option<Channel>,
+        state: bool,
+        color: u8,
+    ) -> Vec<MidiMessage<'static>> {
+        let velocity = match (state, self.use_color) {
+            (true, true) => Velocity::from_u8_lossy(color),
+            (true, false) => self.on_velocity,
+            (false, _) => self.off_velocity,
+        };
+
+        // A remapped pad is only lit on its own channel
+        vec![NoteOn(channel.unwrap_or(self.channel), note, velocity)]
+    }
+}
@@ -1,31 +1,220 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use wmidi::{Channel, ControlFunction, Note, U7};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct MappingConfig {
     toggle_notes: Vec<u8>,
+    /// Notes that don't toggle, toggle_notes don't need an entry
+    #[serde(default)]
+    note_modes: Vec<NoteModeMap>,
+    /// Toggle notes of which only one can be on at a time
+    #[serde(default)]
+    exclusive_groups: Vec<ExclusiveGroup>,
     note_map: Vec<NoteMap>,
     control_map: Vec<ControlMap>,
+    #[serde(default)]
+    banks: Vec<BankMap>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct NoteModeMap {
+    #[serde(alias = "pads")]
+    notes: Vec<u8>,
+    mode: NoteMode,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct ExclusiveGroup {
+    #[serde(alias = "pads")]
+    notes: Vec<u8>,
+}
+
+/// How a note of the controller is handled
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteMode {
+    /// Every press flips the state, the LED shows the state
+    Toggle,
+    /// NoteOn on press and NoteOff on release, the LED is lit while held
+    Momentary,
+    /// Sent to the software as it is, without state and LED
+    Passthrough,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct NoteMap {
+    #[serde(alias = "pad")]
     note: u8,
     new_note: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct ControlMap {
     note: u8,
     new_note: Vec<u8>,
 }
 
+/// Output of a bank towards the software, banks without an entry use the MIDI
+/// channel matching their index and no offset
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct BankMap {
+    channel: u8,
+    #[serde(default)]
+    note_offset: u8,
+}
+
 impl MappingConfig {
-    pub fn new(config: MappingConfig) -> Self {
+    pub fn new(mut config: MappingConfig) -> Self {
+        // Notes with mode = "toggle" are the same as toggle_notes
+        for modes in &config.note_modes {
+            if modes.mode == NoteMode::Toggle {
+                config.toggle_notes.extend(&modes.notes);
+            }
+        }
+        config
+            .note_modes
+            .retain(|modes| modes.mode != NoteMode::Toggle);
+
         config
     }
 
+    pub fn validate(&self, banks: u8) -> Result<()> {
+        if let Some(note) = self.toggle_notes.iter().find(|&&note| note > 127) {
+            return Err(anyhow!("Toggle note {} is out of range (0-127)", note));
+        }
+
+        let mut mode_notes = self.toggle_notes.clone();
+
+        for note in self.note_modes.iter().flat_map(|modes| &modes.notes) {
+            if *note > 127 {
+                return Err(anyhow!(
+                    "Note {} of note_modes is out of range (0-127)",
+                    note
+                ));
+            }
+
+            if mode_notes.contains(note) {
+                return Err(anyhow!("Note {} has more than one mode", note));
+            }
+
+            mode_notes.push(*note);
+        }
+
+        // The config isn't normalized by new yet, so toggles can also be in note_modes
+        let toggle_notes = self
+            .note_modes
+            .iter()
+            .filter(|modes| modes.mode == NoteMode::Toggle)
+            .flat_map(|modes| &modes.notes)
+            .chain(&self.toggle_notes)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut group_notes = Vec::new();
+
+        for note in self.exclusive_groups.iter().flat_map(|group| &group.notes) {
+            if !toggle_notes.contains(note) {
+                return Err(anyhow!(
+                    "Note {} of exclusive_groups is not a toggle note",
+                    note
+                ));
+            }
+
+            if group_notes.contains(note) {
+                return Err(anyhow!("Note {} is in more than one exclusive group", note));
+            }
+
+            group_notes.push(*note);
+        }
+
+        let maps = self
+            .note_map
+            .iter()
+            .map(|map| (map.note, &map.new_note))
+            .chain(self.control_map.iter().map(|map| (map.note, &map.new_note)));
+
+        for (note, new_notes) in maps {
+            if note > 127 || new_notes.iter().any(|&new_note| new_note > 127) {
+                return Err(anyhow!("Map for note {} is out of range (0-127)", note));
+            }
+        }
+
+        for (bank, map) in self.banks.iter().enumerate() {
+            if !(1..=16).contains(&map.channel) {
+                return Err(anyhow!(
+                    "Channel {} of bank {} is out of range (1-16)",
+                    map.channel,
+                    bank
+                ));
+            }
+
+            if map.note_offset > 127 {
+                return Err(anyhow!(
+                    "Note offset {} of bank {} is out of range (0-127)",
+                    map.note_offset,
+                    bank
+                ));
+            }
+        }
+
+        // Feedback finds the bank by channel and offset, so no two banks may send the same note
+        let mut software_notes = HashMap::new();
+
+        for bank in 0..banks {
+            let channel = self.get_bank_channel(bank);
+            let offset = self.get_bank_offset(bank);
+
+            for &note in &toggle_notes {
+                let Some(software_note) = note.checked_add(offset).filter(|&note| note <= 127)
+                else {
+                    continue;
+                };
+
+                if let Some(other_bank) =
+                    software_notes.insert((channel.index(), software_note), bank)
+                {
+                    return Err(anyhow!(
+                        "Banks {} and {} both send note {} on channel {}, give them different channels or note offsets",
+                        other_bank,
+                        bank,
+                        software_note,
+                        channel.index() + 1
+                    ));
+                }
+            }
+        }
+
+        for map in &self.control_map {
+            if self
+                .note_map
+                .iter()
+                .any(|note_map| note_map.note == map.note)
+            {
+                warn!(
+                    "Number {} is in note_map and control_map, notes and controls are remapped independently",
+                    map.note
+                );
+            }
+        }
+
+        let mut new_notes = Vec::new();
+
+        for &new_note in self.note_map.iter().flat_map(|map| &map.new_note) {
+            if new_notes.contains(&new_note) {
+                warn!(
+                    "Note {} is the target of more than one note_map entry, its LED is shown on the first one",
+                    new_note
+                );
+            }
+
+            new_notes.push(new_note);
+        }
+
+        Ok(())
+    }
+
     pub fn is_toggle_note(&self, conn_note: Note) -> bool {
         self.toggle_notes.contains(&u8::from(conn_note))
     }
@@ -34,6 +223,138 @@ impl MappingConfig {
         &self.toggle_notes
     }
 
+    /// All notes of the exclusive group the note is in, including the note itself
+    pub fn get_exclusive_group(&self, conn_note: Note) -> Option<&Vec<u8>> {
+        self.exclusive_groups
+            .iter()
+            .find(|group| group.notes.contains(&u8::from(conn_note)))
+            .map(|group| &group.notes)
+    }
+
+    pub fn get_note_mode(&self, conn_note: Note) -> Option<NoteMode> {
+        let note = u8::from(conn_note);
+
+        if self.toggle_notes.contains(&note) {
+            return Some(NoteMode::Toggle);
+        }
+
+        self.note_modes
+            .iter()
+            .find(|modes| modes.notes.contains(&note))
+            .map(|modes| modes.mode)
+    }
+
+    /// Toggle and momentary notes, their LEDs are repainted on a bank switch
+    pub fn get_led_notes(&self) -> Vec<u8> {
+        let momentary_notes = self
+            .note_modes
+            .iter()
+            .filter(|modes| modes.mode == NoteMode::Momentary)
+            .flat_map(|modes| &modes.notes);
+
+        self.toggle_notes
+            .iter()
+            .chain(momentary_notes)
+            .copied()
+            .collect()
+    }
+
+    pub fn get_bank_channel(&self, bank: u8) -> Channel {
+        match self.banks.get(bank as usize) {
+            Some(map) => Channel::from_index(map.channel - 1).unwrap_or(Channel::Ch1),
+            None => Channel::from_index(bank % 16).unwrap_or(Channel::Ch1),
+        }
+    }
+
+    fn get_bank_offset(&self, bank: u8) -> u8 {
+        self.banks
+            .get(bank as usize)
+            .map(|map| map.note_offset)
+            .unwrap_or(0)
+    }
+
+    /// Channel and note the software receives for a note of a bank
+    pub fn get_software_note(&self, bank: u8, note: Note) -> Result<(Channel, Note)> {
+        let software_note = u8::from(note) + self.get_bank_offset(bank);
+        let software_note = Note::try_from(software_note)
+            .map_err(|_| anyhow!("Note {} with offset of bank {} is out of range", note, bank))?;
+
+        Ok((self.get_bank_channel(bank), software_note))
+    }
+
+    /// Channel and note the software echoes for every toggle note of every bank
+    pub fn get_feedback_notes(&self, banks: u8) -> Vec<(Channel, Note)> {
+        (0..banks)
+            .flat_map(|bank| {
+                self.toggle_notes.iter().filter_map(move |&note| {
+                    self.get_software_note(bank, Note::from_u8_lossy(note)).ok()
+                })
+            })
+            .collect()
+    }
+
+    /// Bank and note for a note the software sends back, the first matching bank wins
+    pub fn get_bank_note(&self, banks: u8, channel: Channel, note: Note) -> Option<(u8, Note)> {
+        (0..banks).find_map(|bank| {
+            if self.get_bank_channel(bank) != channel {
+                return None;
+            }
+
+            let bank_note = u8::from(note).checked_sub(self.get_bank_offset(bank))?;
+            let bank_note = Note::from_u8_lossy(bank_note);
+
+            self.is_toggle_note(bank_note).then_some((bank, bank_note))
+        })
+    }
+
+    /// Whether the software note belongs to a note of any mode, not only a toggle
+    pub fn has_bank_note(&self, banks: u8, channel: Channel, note: Note) -> bool {
+        (0..banks).any(|bank| {
+            self.get_bank_channel(bank) == channel
+                && u8::from(note)
+                    .checked_sub(self.get_bank_offset(bank))
+                    .is_some_and(|bank_note| {
+                        self.get_note_mode(Note::from_u8_lossy(bank_note)).is_some()
+                    })
+        })
+    }
+
+    /// Channel and note of the controller for every remapped note, the inverse of `remap_note`.
+    /// If a note is the target of several maps, the first one wins.
+    pub fn get_controller_notes(&self) -> HashMap<u8, (Channel, Note)> {
+        let mut controller_notes = HashMap::new();
+
+        for map in &self.note_map {
+            for (index, &new_note) in map.new_note.iter().enumerate() {
+                if let Ok(channel) = Channel::from_index(index as u8) {
+                    controller_notes
+                        .entry(new_note)
+                        .or_insert((channel, Note::from_u8_lossy(map.note)));
+                }
+            }
+        }
+
+        controller_notes
+    }
+
+    /// Bank a control from the software belongs to, the first bank on its channel wins
+    pub fn get_control_bank(&self, banks: u8, channel: Channel) -> Option<u8> {
+        (0..banks).find(|&bank| self.get_bank_channel(bank) == channel)
+    }
+
+    /// Channel and control of the controller for a remapped control, the inverse of `remap_control`
+    pub fn get_controller_control(&self, control: u8) -> Option<(Channel, u8)> {
+        self.control_map.iter().find_map(|map| {
+            let index = map
+                .new_note
+                .iter()
+                .position(|&new_note| new_note == control)?;
+            let channel = Channel::from_index(index as u8).ok()?;
+
+            Some((channel, map.note))
+        })
+    }
+
     pub fn remap_note(&self, channel: &Channel, conn_note: Note) -> Result<Note> {
         for map in &self.note_map {
             if map.note == u8::from(conn_note) {
@@ -53,7 +374,7 @@ impl MappingConfig {
         channel: &Channel,
         conn_note: ControlFunction,
     ) -> Result<ControlFunction> {
-        for map in &self.note_map {
+        for map in &self.control_map {
             if map.note == u8::from(conn_note) {
                 return if let Some(&new_note) = map.new_note.get(channel.index() as usize) {
                     Ok(ControlFunction::from(U7::from_u8_lossy(new_note)))
@@ -66,3 +387,115 @@ impl MappingConfig {
         Ok(conn_note)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping_config(maps: &str) -> MappingConfig {
+        MappingConfig::new(toml::from_str(maps).unwrap())
+    }
+
+    fn control(number: u8) -> ControlFunction {
+        ControlFunction::from(U7::from_u8_lossy(number))
+    }
+
+    #[test]
+    fn note_map_does_not_remap_controls() {
+        let config = mapping_config(
+            r#"
+            toggle_notes = []
+            note_map = [{ note = 48, new_note = [40, 41] }]
+            control_map = []
+            "#,
+        );
+
+        assert_eq!(
+            config
+                .remap_note(&Channel::Ch2, Note::from_u8_lossy(48))
+                .unwrap(),
+            Note::from_u8_lossy(41)
+        );
+        assert_eq!(
+            config.remap_control(&Channel::Ch2, control(48)).unwrap(),
+            control(48)
+        );
+    }
+
+    #[test]
+    fn control_map_does_not_remap_notes() {
+        let config = mapping_config(
+            r#"
+            toggle_notes = []
+            note_map = []
+            control_map = [{ note = 7, new_note = [0, 1] }]
+            "#,
+        );
+
+        assert_eq!(
+            config.remap_control(&Channel::Ch2, control(7)).unwrap(),
+            control(1)
+        );
+        assert_eq!(
+            config
+                .remap_note(&Channel::Ch2, Note::from_u8_lossy(7))
+                .unwrap(),
+            Note::from_u8_lossy(7)
+        );
+    }
+
+    #[test]
+    fn number_in_both_maps_is_remapped_independently() {
+        let config = mapping_config(
+            r#"
+            toggle_notes = []
+            note_map = [{ note = 7, new_note = [40, 41] }]
+            control_map = [{ note = 7, new_note = [0, 1] }]
+            "#,
+        );
+
+        assert_eq!(
+            config
+                .remap_note(&Channel::Ch2, Note::from_u8_lossy(7))
+                .unwrap(),
+            Note::from_u8_lossy(41)
+        );
+        assert_eq!(
+            config.remap_control(&Channel::Ch2, control(7)).unwrap(),
+            control(1)
+        );
+    }
+
+    #[test]
+    fn exclusive_group_accepts_toggle_mode_notes() {
+        // Config::validate checks the maps as written, before new merges the modes
+        let maps: MappingConfig = toml::from_str(
+            r#"
+            toggle_notes = [0]
+            note_modes = [{ notes = [1, 2], mode = "toggle" }, { notes = [3], mode = "momentary" }]
+            exclusive_groups = [{ notes = [0, 1, 2] }]
+            note_map = []
+            control_map = []
+            "#,
+        )
+        .unwrap();
+
+        assert!(maps.validate(1).is_ok());
+    }
+
+    #[test]
+    fn exclusive_group_rejects_momentary_notes() {
+        let maps: MappingConfig = toml::from_str(
+            r#"
+            toggle_notes = [0]
+            note_modes = [{ notes = [3], mode = "momentary" }]
+            exclusive_groups = [{ notes = [0, 3] }]
+            note_map = []
+            control_map = []
+            "#,
+        )
+        .unwrap();
+
+        assert!(maps.validate(1).is_err());
+    }
+}
@@ -1,127 +1,335 @@
-use crate::router::mapping_config::MappingConfig;
-use crate::router::{
-    input_connection::{
-        InputConnection,
-        InputMessage::{ControllerMessage, SoftwareMessage},
+use crate::{
+    router::{
+        input_connection::{
+            InputConnection,
+            InputMessage::{ControllerMessage, SoftwareMessage},
+        },
+        port_match::{PortMatchConfig, PortRole},
+        router_handle::RouterHandle,
     },
-    midi_handler::MidiHandler,
-    output_connection::OutputConnection,
+    utils::config::RouterConfig,
 };
-use anyhow::{Context, Result, anyhow};
-use log::{info, warn};
-use midir::{MidiIO, MidiInput, MidiInputPort, MidiOutput, MidiOutputPort};
-use std::sync::{Arc, Mutex};
+use anyhow::{Context, Result};
+use log::info;
+use midir::{MidiInput, MidiInputPort, MidiOutput, MidiOutputPort};
+use serde::Serialize;
 
 pub struct MidiRouter {
-    from_controller_connection: InputConnection,
-    to_controller_connection: Arc<Mutex<OutputConnection>>,
-    from_software_connection: InputConnection,
-    to_software_connection: Arc<Mutex<OutputConnection>>,
-    midi_handler: Arc<Mutex<MidiHandler>>,
+    from_controller_connections: Vec<InputConnection>,
+    from_software_connections: Vec<InputConnection>,
+    router: RouterHandle,
+}
+
+/// MIDI ports seen by midir, with the roles the config would use them for
+#[derive(Debug, Serialize)]
+pub struct PortList {
+    pub(crate) inputs: Vec<PortInfo>,
+    pub(crate) outputs: Vec<PortInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortInfo {
+    pub(crate) index: usize,
+    pub(crate) name: String,
+    pub(crate) roles: Vec<String>,
 }
 
 struct MidiConnections {
-    from_controller_name: String,
-    to_controller_name: String,
+    controllers: Vec<ControllerConnections>,
+    software: Vec<SoftwareConnections>,
+}
+
+struct SoftwareConnections {
     from_software_name: String,
     to_software_name: String,
+    from_software_midi_name: String,
+    to_software_midi_name: String,
 
-    from_controller_midi: MidiInput,
-    to_controller_midi: MidiOutput,
     from_software_midi: MidiInput,
     to_software_midi: MidiOutput,
 
+    from_software_port: Option<MidiInputPort>,
+    to_software_port: Option<MidiOutputPort>,
+}
+
+struct ControllerConnections {
+    from_controller_name: String,
+    to_controller_name: String,
+
+    from_controller_midi: MidiInput,
+    to_controller_midi: MidiOutput,
+
     from_controller_port: MidiInputPort,
     to_controller_port: MidiOutputPort,
-    from_software_port: MidiInputPort,
-    to_software_port: MidiOutputPort,
 }
 
 impl MidiRouter {
-    pub fn new(config: MappingConfig) -> Self {
+    pub fn new(router: RouterHandle) -> Self {
         Self {
-            from_controller_connection: InputConnection::new(),
-            to_controller_connection: Arc::new(Mutex::new(OutputConnection::new())),
-            from_software_connection: InputConnection::new(),
-            to_software_connection: Arc::new(Mutex::new(OutputConnection::new())),
-            midi_handler: Arc::new(Mutex::new(MidiHandler::new(config))),
+            from_controller_connections: Vec::new(),
+            from_software_connections: Vec::new(),
+            router,
         }
     }
 
-    pub fn connect(&mut self, controller_name: &str, software_name: &str) -> Result<()> {
-        let connections = self.setup_midi_connections(controller_name, software_name)?;
+    pub fn connect(&mut self, config: &RouterConfig) -> Result<()> {
+        let connections = self.setup_midi_connections(config)?;
 
         self.connect_midi_devices(connections)?;
         Ok(())
     }
 
-    fn setup_midi_connections(
-        &self,
-        controller_name: &str,
+    /// Lists all input and output ports and marks the ones the config matches
+    pub fn list_ports(config: Option<&RouterConfig>) -> Result<PortList> {
+        let midi_input =
+            MidiInput::new("midi-router-list").context("Failed to create MIDI input")?;
+        let midi_output =
+            MidiOutput::new("midi-router-list").context("Failed to create MIDI output")?;
+
+        let input_ports = midi_input.ports();
+        let output_ports = midi_output.ports();
+
+        let mut inputs = input_ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| PortInfo {
+                index,
+                name: midi_input.port_name(port).unwrap_or_default(),
+                roles: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut outputs = output_ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| PortInfo {
+                index,
+                name: midi_output.port_name(port).unwrap_or_default(),
+                roles: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(config) = config {
+            let port_match = &config.port_match;
+
+            let mut input_roles = Vec::new();
+            let mut output_roles = Vec::new();
+
+            for name in config.get_controller_names() {
+                input_roles.push((name.to_string(), PortRole::ControllerInput));
+                output_roles.push((name.to_string(), PortRole::ControllerOutput));
+            }
+
+            if !config.virtual_ports {
+                for name in config.get_software_names() {
+                    input_roles.push((name.to_string(), PortRole::SoftwareInput));
+                    output_roles.push((name.to_string(), PortRole::SoftwareOutput));
+                }
+            }
+
+            for (name, role) in input_roles {
+                if let Some(index) =
+                    port_match.get_port_index(&midi_input, &input_ports, &name, role)
+                {
+                    inputs[index]
+                        .roles
+                        .push(format!("{} ({}{})", role, role.prefix(), name));
+                }
+            }
+
+            for (name, role) in output_roles {
+                if let Some(index) =
+                    port_match.get_port_index(&midi_output, &output_ports, &name, role)
+                {
+                    outputs[index]
+                        .roles
+                        .push(format!("{} ({}{})", role, role.prefix(), name));
+                }
+            }
+        }
+
+        Ok(PortList { inputs, outputs })
+    }
+
+    /// Whether all configured ports exist, virtual software ports always do
+    pub fn ports_available(config: &RouterConfig) -> bool {
+        Self::missing_device(config).is_none()
+    }
+
+    /// The first device without a matching port, e.g. "controller APC40 mkII" or "software Daslight"
+    pub fn missing_device(config: &RouterConfig) -> Option<String> {
+        let (Ok(midi_input), Ok(midi_output)) = (
+            MidiInput::new("midi-router-probe"),
+            MidiOutput::new("midi-router-probe"),
+        ) else {
+            return Some("MIDI".to_string());
+        };
+
+        let input_ports = midi_input.ports();
+        let output_ports = midi_output.ports();
+        let port_match = &config.port_match;
+
+        for name in config.get_controller_names() {
+            let controller_available =
+                port_match.has_port(&midi_input, &input_ports, name, PortRole::ControllerInput)
+                    && port_match.has_port(
+                        &midi_output,
+                        &output_ports,
+                        name,
+                        PortRole::ControllerOutput,
+                    );
+
+            if !controller_available {
+                return Some(format!("controller {}", name));
+            }
+        }
+
+        if config.virtual_ports {
+            return None;
+        }
+
+        config
+            .get_software_names()
+            .into_iter()
+            .find(|name| {
+                !(port_match.has_port(&midi_input, &input_ports, name, PortRole::SoftwareInput)
+                    && port_match.has_port(
+                        &midi_output,
+                        &output_ports,
+                        name,
+                        PortRole::SoftwareOutput,
+                    ))
+            })
+            .map(|name| format!("software {}", name))
+    }
+
+    fn setup_midi_connections(&self, config: &RouterConfig) -> Result<MidiConnections> {
+        let port_match = &config.port_match;
+
+        let controllers = config
+            .get_controller_names()
+            .into_iter()
+            .map(|name| Self::setup_controller_connections(name, port_match))
+            .collect::<Result<Vec<_>>>()?;
+
+        let software = config
+            .get_software_names()
+            .into_iter()
+            .map(|name| Self::setup_software_connections(name, config.virtual_ports, port_match))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(MidiConnections {
+            controllers,
+            software,
+        })
+    }
+
+    fn setup_software_connections(
         software_name: &str,
-    ) -> Result<MidiConnections> {
+        virtual_ports: bool,
+        port_match: &PortMatchConfig,
+    ) -> Result<SoftwareConnections> {
         let from_software_midi_name = format!("from_{}", software_name);
         let to_software_midi_name = format!("to_{}", software_name);
 
-        let from_controller_name = format!("{}-router-input", controller_name.to_lowercase());
-        let to_controller_name = format!("{}-router-output", controller_name.to_lowercase());
         let from_software_name = format!("{}-router-input", software_name.to_lowercase());
         let to_software_name = format!("{}-router-output", software_name.to_lowercase());
 
         // Create MIDI interfaces
-        let from_controller_midi = MidiInput::new(&from_controller_name)
-            .context("Failed to create controller MIDI input")?;
-        let to_controller_midi = MidiOutput::new(&to_controller_name)
-            .context("Failed to create controller MIDI output")?;
         let from_software_midi =
             MidiInput::new(&from_software_name).context("Failed to create software MIDI input")?;
         let to_software_midi =
             MidiOutput::new(&to_software_name).context("Failed to create software MIDI output")?;
 
+        // Virtual ports are created by the router itself while connecting
+        let (from_software_port, to_software_port) = if virtual_ports {
+            (None, None)
+        } else {
+            let from_software_ports = from_software_midi.ports();
+            let to_software_ports = to_software_midi.ports();
+
+            let from_software_port = port_match.find_port(
+                &from_software_midi,
+                &from_software_ports,
+                software_name,
+                PortRole::SoftwareInput,
+            )?;
+            let to_software_port = port_match.find_port(
+                &to_software_midi,
+                &to_software_ports,
+                software_name,
+                PortRole::SoftwareOutput,
+            )?;
+
+            (Some(from_software_port), Some(to_software_port))
+        };
+
+        match (&from_software_port, &to_software_port) {
+            (Some(from_software_port), Some(to_software_port)) => {
+                info!(
+                    "Using Software feedback input: {}",
+                    from_software_midi.port_name(from_software_port)?
+                );
+                info!(
+                    "Using Software output: {}",
+                    to_software_midi.port_name(to_software_port)?
+                );
+            }
+            _ => {
+                info!(
+                    "Creating virtual Software feedback input: {}",
+                    from_software_midi_name
+                );
+                info!(
+                    "Creating virtual Software output: {}",
+                    to_software_midi_name
+                );
+            }
+        }
+
+        Ok(SoftwareConnections {
+            from_software_name,
+            to_software_name,
+            from_software_midi_name,
+            to_software_midi_name,
+
+            from_software_midi,
+            to_software_midi,
+
+            from_software_port,
+            to_software_port,
+        })
+    }
+
+    fn setup_controller_connections(
+        controller_name: &str,
+        port_match: &PortMatchConfig,
+    ) -> Result<ControllerConnections> {
+        let from_controller_name = format!("{}-router-input", controller_name.to_lowercase());
+        let to_controller_name = format!("{}-router-output", controller_name.to_lowercase());
+
+        // Create MIDI interfaces
+        let from_controller_midi = MidiInput::new(&from_controller_name)
+            .context("Failed to create controller MIDI input")?;
+        let to_controller_midi = MidiOutput::new(&to_controller_name)
+            .context("Failed to create controller MIDI output")?;
+
         // Fetch ports
         let from_controller_ports = from_controller_midi.ports();
         let to_controller_ports = to_controller_midi.ports();
-        let from_software_ports = from_software_midi.ports();
-        let to_software_ports = to_software_midi.ports();
 
-        // Try to get ports (gracefully)
-        let from_controller_port = find_port(
+        let from_controller_port = port_match.find_port(
             &from_controller_midi,
             &from_controller_ports,
             controller_name,
+            PortRole::ControllerInput,
         )?;
-        let to_controller_port =
-            find_port(&to_controller_midi, &to_controller_ports, controller_name)?;
-        let from_software_port = find_port(
-            &from_software_midi,
-            &from_software_ports,
-            &from_software_midi_name,
-        )?;
-        let to_software_port = find_port(
-            &to_software_midi,
-            &to_software_ports,
-            &to_software_midi_name,
+        let to_controller_port = port_match.find_port(
+            &to_controller_midi,
+            &to_controller_ports,
+            controller_name,
+            PortRole::ControllerOutput,
         )?;
 
-        /// Helper to safely find a port (with fallback + warning)
-        fn find_port<P: MidiIO>(midi_io: &P, ports: &[P::Port], name: &str) -> Result<P::Port> {
-            if ports.is_empty() {
-                return Err(anyhow!("No MIDI ports available for '{}'", name));
-            }
-
-            if let Some(port) = ports.iter().find(|port| {
-                midi_io
-                    .port_name(port)
-                    .map(|n| n.to_lowercase().contains(&name.to_lowercase()))
-                    .unwrap_or(false)
-            }) {
-                Ok(port.clone())
-            } else {
-                warn!("No matching port for '{}', using first available.", name);
-                Ok(ports[0].clone())
-            }
-        }
-
         info!(
             "Using Controller input: {}",
             from_controller_midi.port_name(&from_controller_port)?
@@ -130,73 +338,77 @@ impl MidiRouter {
             "Using Controller feedback output: {}",
             to_controller_midi.port_name(&to_controller_port)?
         );
-        info!(
-            "Using Software feedback input: {}",
-            from_software_midi.port_name(&from_software_port)?
-        );
-        info!(
-            "Using Software output: {}",
-            to_software_midi.port_name(&to_software_port)?
-        );
 
-        Ok(MidiConnections {
+        Ok(ControllerConnections {
             from_controller_name,
             to_controller_name,
-            from_software_name,
-            to_software_name,
 
             from_controller_midi,
             to_controller_midi,
-            from_software_midi,
-            to_software_midi,
 
             from_controller_port,
             to_controller_port,
-            from_software_port,
-            to_software_port,
         })
     }
 
     fn connect_midi_devices(&mut self, connections: MidiConnections) -> Result<()> {
-        let handler = Arc::clone(&self.midi_handler);
-        let to_controller = Arc::clone(&self.to_controller_connection);
-        let to_software = Arc::clone(&self.to_software_connection);
-
-        self.from_controller_connection.connect(
-            &connections.from_controller_name,
-            connections.from_controller_midi,
-            &connections.from_controller_port,
-            handler.clone(),
-            to_controller.clone(),
-            to_software.clone(),
-            ControllerMessage,
-        )?;
-
-        {
-            let mut to_controller_lock = to_controller.lock().unwrap();
-            to_controller_lock.connect(
-                &connections.to_controller_name,
-                connections.to_controller_midi,
-                &connections.to_controller_port,
+        for (index, controller) in connections.controllers.into_iter().enumerate() {
+            let mut from_controller_connection = InputConnection::new();
+
+            from_controller_connection.connect(
+                &controller.from_controller_name,
+                controller.from_controller_midi,
+                &controller.from_controller_port,
+                self.router.clone(),
+                ControllerMessage(index),
+            )?;
+            self.from_controller_connections
+                .push(from_controller_connection);
+
+            self.router.connect_to_controller(
+                index,
+                &controller.to_controller_name,
+                controller.to_controller_midi,
+                &controller.to_controller_port,
             )?;
         }
 
-        self.from_software_connection.connect(
-            &connections.from_software_name,
-            connections.from_software_midi,
-            &connections.from_software_port,
-            handler,
-            to_controller,
-            to_software,
-            SoftwareMessage,
-        )?;
-
-        let mut to_software_lock = self.to_software_connection.lock().unwrap();
-        to_software_lock.connect(
-            &connections.to_software_name,
-            connections.to_software_midi,
-            &connections.to_software_port,
-        )?;
+        // Feedback of all software targets is merged into the controller states
+        for (index, software) in connections.software.into_iter().enumerate() {
+            let mut from_software_connection = InputConnection::new();
+
+            match &software.from_software_port {
+                Some(port) => from_software_connection.connect(
+                    &software.from_software_name,
+                    software.from_software_midi,
+                    port,
+                    self.router.clone(),
+                    SoftwareMessage,
+                )?,
+                None => from_software_connection.create_virtual(
+                    &software.from_software_midi_name,
+                    software.from_software_midi,
+                    self.router.clone(),
+                    SoftwareMessage,
+                )?,
+            }
+            self.from_software_connections
+                .push(from_software_connection);
+
+            match &software.to_software_port {
+                Some(port) => self.router.connect_to_software(
+                    index,
+                    &software.to_software_name,
+                    software.to_software_midi,
+                    port,
+                )?,
+                None => self.router.create_virtual_software(
+                    index,
+                    &software.to_software_midi_name,
+                    software.to_software_midi,
+                )?,
+            }
+        }
 
         Ok(())
     }
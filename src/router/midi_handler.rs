@@ -1,84 +1,263 @@
-use crate::router::{
-    led_controller::LedController, mapping_config::MappingConfig,
-    output_connection::OutputConnection, state_manager::StateManager,
+use crate::{
+    router::{
+        bank_switch::BankSwitchConfig,
+        feedback::{FeedbackConfig, FeedbackTracker},
+        led_controller::LedController,
+        mapping_config::{MappingConfig, NoteMode},
+        output_connection::OutputConnection,
+        rule_engine::{RuleEngine, RuleOutput},
+        software_output::SoftwareOutputs,
+        state_manager::{NoteState, StateManager},
+    },
+    utils::config::ControllerConfig,
 };
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use log::{debug, trace, warn};
+use std::collections::HashMap;
 use wmidi::{
-    Channel, MidiMessage,
+    Channel, ControlFunction, MidiMessage,
     MidiMessage::{ControlChange, NoteOff, NoteOn},
-    Note, Velocity,
+    Note, U7, Velocity,
 };
 
 pub struct MidiHandler {
     state_manager: StateManager,
     mapping_config: MappingConfig,
+    bank_switch: BankSwitchConfig,
     led_controller: LedController,
+    rule_engine: RuleEngine,
+    feedback: FeedbackTracker,
+    /// Bank of every held momentary note, so the release goes to the same bank
+    held_notes: HashMap<u8, u8>,
 }
 
 impl MidiHandler {
-    pub fn new(config: MappingConfig) -> Self {
+    pub fn new(config: &ControllerConfig, feedback: &FeedbackConfig) -> Self {
+        let mapping_config = MappingConfig::new(config.maps.clone());
+
         Self {
-            state_manager: StateManager::new(),
-            mapping_config: MappingConfig::new(config),
-            led_controller: LedController::new(),
+            state_manager: StateManager::new(config.bank_switch.get_banks()),
+            led_controller: LedController::new(&config.led, &mapping_config),
+            mapping_config,
+            bank_switch: config.bank_switch.clone(),
+            rule_engine: RuleEngine::new(&config.rules),
+            feedback: FeedbackTracker::new(feedback),
+            held_notes: HashMap::new(),
         }
     }
 
-    pub fn _get_state_manager(&self) -> &StateManager {
+    pub fn get_state_manager(&self) -> &StateManager {
         &self.state_manager
     }
 
+    pub fn get_state_manager_mut(&mut self) -> &mut StateManager {
+        &mut self.state_manager
+    }
+
+    /// Swaps in a reloaded config, the toggle states are kept
+    pub fn set_config(&mut self, config: &ControllerConfig, feedback: &FeedbackConfig) {
+        self.mapping_config = MappingConfig::new(config.maps.clone());
+        self.state_manager
+            .set_bank_count(config.bank_switch.get_banks());
+        self.bank_switch = config.bank_switch.clone();
+        self.led_controller = LedController::new(&config.led, &self.mapping_config);
+        self.rule_engine = RuleEngine::new(&config.rules);
+        self.feedback.set_config(feedback);
+    }
+
+    /// Repaints the LEDs and sends the stored control values of the current bank
+    pub fn refresh_leds(&mut self, to_controller_connection: &mut OutputConnection) -> Result<()> {
+        let current_bank = self.state_manager.get_current_bank();
+
+        self.led_controller.refresh_all_leds(
+            to_controller_connection,
+            &mut self.state_manager,
+            current_bank,
+            &self.mapping_config.get_led_notes(),
+        )?;
+
+        for (control, value) in self.state_manager.get_control_values(current_bank) {
+            self.send_control(to_controller_connection, control, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reverts toggles the software didn't confirm in time and repaints all LEDs when a repaint is due
+    pub fn check_feedback(
+        &mut self,
+        to_controller_connection: &mut OutputConnection,
+    ) -> Result<()> {
+        for (bank, note, previous_state) in self.feedback.take_expired() {
+            let note = Note::from_u8_lossy(note);
+            warn!(
+                "Software didn't confirm note {} of bank {}, reverted",
+                u8::from(note),
+                bank + 1
+            );
+
+            self.state_manager
+                .set_note_state(bank, note, previous_state)?;
+
+            self.refresh_visible_led(to_controller_connection, bank, note)?;
+        }
+
+        if self.feedback.is_repaint_due() {
+            self.refresh_leds(to_controller_connection)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_bank_notes(&mut self, bank: u8) -> Result<Vec<NoteState>> {
+        let toggle_notes = self.mapping_config.get_toggle_notes().clone();
+
+        toggle_notes
+            .into_iter()
+            .map(|note_u8| self.get_note(bank, Note::from_u8_lossy(note_u8)))
+            .collect()
+    }
+
+    pub fn get_note(&mut self, bank: u8, note: Note) -> Result<NoteState> {
+        let (state, color) = self
+            .state_manager
+            .get_note_state_and_color(bank, note)
+            .map_err(|_| anyhow!("Bank {} doesn't exist", bank))?;
+
+        Ok(NoteState {
+            note: u8::from(note),
+            state: *state,
+            color: *color,
+        })
+    }
+
+    pub fn set_note(
+        &mut self,
+        bank: u8,
+        note: Note,
+        new_state: Option<bool>,
+        new_color: Option<Velocity>,
+        to_controller_connection: &mut OutputConnection,
+        software_outputs: &mut SoftwareOutputs,
+    ) -> Result<NoteState> {
+        if !self.mapping_config.is_toggle_note(note) {
+            return Err(anyhow!(
+                "Toggle notes doesn't include note: {}",
+                u8::from(note)
+            ));
+        }
+
+        let current = self.get_note(bank, note)?;
+
+        if let Some(color) = new_color {
+            self.state_manager.set_note_color(bank, note, color)?;
+        }
+
+        if let Some(state) = new_state {
+            if state != current.state {
+                // The software toggles on every press, so only send one if the state really changes
+                self.state_manager.set_note_state(bank, note, state)?;
+                self.feedback.expect(bank, u8::from(note), current.state);
+                let (bank_channel, software_note) =
+                    self.mapping_config.get_software_note(bank, note)?;
+                let message = NoteOn(bank_channel, software_note, Velocity::MAX);
+                software_outputs.send(bank, &message)?;
+
+                if state {
+                    self.switch_off_group(software_outputs, bank, note)?;
+                }
+            }
+        }
+
+        if bank == self.state_manager.get_current_bank() {
+            self.refresh_note_leds(to_controller_connection, bank, note)?;
+        }
+
+        self.get_note(bank, note)
+    }
+
     pub fn handle_controller_msg(
         &mut self,
         msg: MidiMessage,
         to_controller_connection: &mut OutputConnection,
-        to_software_connection: &mut OutputConnection,
+        software_outputs: &mut SoftwareOutputs,
     ) -> Result<()> {
         trace!("Received MIDI message from Controller: {:?}", msg);
 
-        self.handle_site_change(&msg, to_controller_connection)?; // Check if user wants to change site
-        self.process_controller_message(msg, to_controller_connection, to_software_connection)?;
+        // Check if user wants to change site
+        if self.handle_site_change(&msg, to_controller_connection)? {
+            return Ok(());
+        }
+
+        let Some(outputs) = self.rule_engine.apply(&msg) else {
+            return self.process_controller_message(
+                msg,
+                to_controller_connection,
+                software_outputs,
+            );
+        };
+
+        for output in outputs {
+            match output {
+                RuleOutput::Default(message) => {
+                    self.process_controller_message(
+                        message,
+                        to_controller_connection,
+                        software_outputs,
+                    )?;
+                }
+                RuleOutput::Software(message) => {
+                    let current_bank = self.state_manager.get_current_bank();
+                    software_outputs.send(current_bank, &message)?;
+                }
+                RuleOutput::Port(name, message) => software_outputs.send_to(&name, &message)?,
+                RuleOutput::SetBank(bank) => self.switch_bank(bank, to_controller_connection)?,
+            }
+        }
 
         Ok(())
     }
 
+    /// Returns false if the message is a note of none of the banks of this controller
     pub fn handle_software_msg(
         &mut self,
         msg: MidiMessage,
         to_controller_connection: &mut OutputConnection,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         trace!("Received MIDI message from Software: {:?}", msg);
 
-        self.process_software_message(msg, to_controller_connection)?;
-
-        Ok(())
+        self.process_software_message(msg, to_controller_connection)
     }
 
+    /// Returns true if the message only switched the bank and shouldn't be processed further
     fn handle_site_change(
         &mut self,
         msg: &MidiMessage,
         to_controller_connection: &mut OutputConnection,
-    ) -> Result<()> {
-        if let ControlChange(channel, control, _velocity) = msg {
-            if u8::from(*control) == 16 {
-                let current_bank = self.state_manager.get_current_bank();
+    ) -> Result<bool> {
+        let current_bank = self.state_manager.get_current_bank();
 
-                if channel != current_bank {
-                    self.state_manager.set_current_bank(*channel);
+        if let Some(bank) = self.bank_switch.target_bank(msg, current_bank) {
+            self.switch_bank(bank, to_controller_connection)?;
+        }
 
-                    self.led_controller.refresh_all_leds(
-                        to_controller_connection,
-                        &mut self.state_manager,
-                        channel,
-                        self.mapping_config.get_toggle_notes(),
-                    )?;
+        Ok(self.bank_switch.is_consumed(msg))
+    }
 
-                    debug!("New Site: {}", format!("{:?}", channel).replace("Ch", ""));
-                }
-            }
+    fn switch_bank(
+        &mut self,
+        bank: u8,
+        to_controller_connection: &mut OutputConnection,
+    ) -> Result<()> {
+        if bank == self.state_manager.get_current_bank() {
+            return Ok(());
         }
 
+        self.state_manager.set_current_bank(bank);
+        self.refresh_leds(to_controller_connection)?;
+
+        debug!("New Site: {}", bank + 1);
+
         Ok(())
     }
 
@@ -86,55 +265,99 @@ impl MidiHandler {
         &mut self,
         midi_message: MidiMessage,
         to_controller_connection: &mut OutputConnection,
-        to_software_connection: &mut OutputConnection,
+        software_outputs: &mut SoftwareOutputs,
     ) -> Result<()> {
-        let current_bank = &self.state_manager.get_current_bank().clone();
+        let current_bank = self.state_manager.get_current_bank();
+
+        // Many controllers release a pad with NoteOn and velocity 0
+        let midi_message = match midi_message {
+            NoteOn(channel, note, velocity) if u8::from(velocity) == 0 => {
+                NoteOff(channel, note, velocity)
+            }
+            message => message,
+        };
+
         match midi_message {
             NoteOn(channel, note, velocity) => {
                 let remapped_note = self.mapping_config.remap_note(&channel, note)?;
 
-                if self.mapping_config.is_toggle_note(remapped_note) {
-                    self.toggle_note_handler(
+                match self.mapping_config.get_note_mode(remapped_note) {
+                    Some(NoteMode::Toggle) => self.toggle_note_handler(
                         to_controller_connection,
-                        to_software_connection,
+                        software_outputs,
                         current_bank,
                         remapped_note,
                         velocity,
-                    )?;
-                } else {
-                    warn!(
-                        "Toggle notes doesn't include note (Controller On): {}",
+                    )?,
+                    Some(NoteMode::Momentary) => self.momentary_note_handler(
+                        to_controller_connection,
+                        software_outputs,
+                        current_bank,
+                        remapped_note,
+                        Some(velocity),
+                    )?,
+                    Some(NoteMode::Passthrough) => {
+                        let (bank_channel, software_note) = self
+                            .mapping_config
+                            .get_software_note(current_bank, remapped_note)?;
+                        let message = NoteOn(bank_channel, software_note, velocity);
+                        software_outputs.send(current_bank, &message)?;
+                    }
+                    None => warn!(
+                        "Maps don't include note (Controller On): {}",
                         u8::from(remapped_note)
-                    );
+                    ),
                 }
             }
 
-            NoteOff(channel, note, _velocity) => {
+            NoteOff(channel, note, velocity) => {
                 let remapped_note = self.mapping_config.remap_note(&channel, note)?;
 
-                if self.mapping_config.is_toggle_note(remapped_note) {
-                    self.led_controller.refresh_single_led(
+                match self.mapping_config.get_note_mode(remapped_note) {
+                    Some(NoteMode::Toggle) => self.led_controller.refresh_single_led(
                         to_controller_connection,
                         &mut self.state_manager,
                         current_bank,
                         remapped_note,
-                    )?;
-                } else {
-                    warn!(
-                        "Toggle notes doesn't include note (Controller Off): {}",
+                    )?,
+                    Some(NoteMode::Momentary) => self.momentary_note_handler(
+                        to_controller_connection,
+                        software_outputs,
+                        current_bank,
+                        remapped_note,
+                        None,
+                    )?,
+                    Some(NoteMode::Passthrough) => {
+                        let (bank_channel, software_note) = self
+                            .mapping_config
+                            .get_software_note(current_bank, remapped_note)?;
+                        let message = NoteOff(bank_channel, software_note, velocity);
+                        software_outputs.send(current_bank, &message)?;
+                    }
+                    None => warn!(
+                        "Maps don't include note (Controller Off): {}",
                         u8::from(remapped_note)
-                    );
+                    ),
                 }
             }
 
             ControlChange(channel, control, velocity) => {
                 let remapped_control = self.mapping_config.remap_control(&channel, control)?;
-                let message = ControlChange(*current_bank, remapped_control, velocity);
-                self.send_midi_message(to_software_connection, message)?;
+                self.state_manager
+                    .set_control_channel(u8::from(remapped_control), channel);
+                self.state_manager.set_control_value(
+                    current_bank,
+                    u8::from(remapped_control),
+                    u8::from(velocity),
+                );
+
+                let bank_channel = self.mapping_config.get_bank_channel(current_bank);
+                let message = ControlChange(bank_channel, remapped_control, velocity);
+                software_outputs.send(current_bank, &message)?;
             }
 
             _ => {
-                self.forward_raw_message(to_software_connection, &midi_message)?;
+                software_outputs.send(current_bank, &midi_message)?;
             }
         }
 
@@ -145,96 +368,329 @@ impl MidiHandler {
         &mut self,
         midi_message: MidiMessage,
         to_controller_connection: &mut OutputConnection,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         match midi_message {
             NoteOn(channel, note, velocity) => {
-                if self.mapping_config.is_toggle_note(note) {
-                    self.state_manager.set_note_state(&channel, note, true)?;
-                    self.state_manager
-                        .set_note_color(&channel, note, velocity)?;
+                let Some((bank, bank_note)) = self.get_bank_note(channel, note) else {
+                    return Ok(self.is_echo(channel, note));
+                };
 
-                    self.led_controller.refresh_single_led(
-                        to_controller_connection,
-                        &mut self.state_manager,
-                        &channel,
-                        note,
-                    )?;
-                } else {
-                    warn!(
-                        "Toggle notes doesn't include note (Software On): {}",
-                        u8::from(note)
-                    );
-                }
+                self.state_manager.set_note_state(bank, bank_note, true)?;
+                self.feedback.confirm(bank, u8::from(bank_note));
+                self.state_manager
+                    .set_note_color(bank, bank_note, velocity)?;
+
+                self.refresh_visible_led(to_controller_connection, bank, bank_note)?;
             }
 
             NoteOff(channel, note, _velocity) => {
-                if self.mapping_config.is_toggle_note(note) {
-                    self.state_manager.set_note_state(&channel, note, false)?;
+                let Some((bank, bank_note)) = self.get_bank_note(channel, note) else {
+                    return Ok(self.is_echo(channel, note));
+                };
+
+                self.state_manager.set_note_state(bank, bank_note, false)?;
+                self.feedback.confirm(bank, u8::from(bank_note));
+
+                self.refresh_visible_led(to_controller_connection, bank, bank_note)?;
+            }
 
-                    self.led_controller.refresh_single_led(
+            ControlChange(channel, control, value) => {
+                let banks = self.state_manager.get_bank_count();
+                let Some(bank) = self.mapping_config.get_control_bank(banks, channel) else {
+                    return Ok(false);
+                };
+
+                self.state_manager
+                    .set_control_value(bank, u8::from(control), u8::from(value));
+
+                if bank == self.state_manager.get_current_bank() {
+                    self.send_control(
                         to_controller_connection,
-                        &mut self.state_manager,
-                        &channel,
-                        note,
+                        u8::from(control),
+                        u8::from(value),
                     )?;
-                } else {
-                    warn!(
-                        "Toggle notes doesn't include note (Software Off): {}",
-                        u8::from(note)
-                    );
                 }
             }
 
-            ControlChange(_, _, _) => {}
             _ => {}
         }
 
-        Ok(())
+        Ok(true)
     }
 
-    fn toggle_note_handler(
+    /// Only the current bank is shown on the controller, the other banks are painted on a bank switch
+    fn refresh_visible_led(
         &mut self,
         to_controller_connection: &mut OutputConnection,
-        to_software_connection: &mut OutputConnection,
-        bank: &Channel,
+        bank: u8,
         note: Note,
-        velocity: Velocity,
     ) -> Result<()> {
-        self.state_manager.toggle_note_state(bank, note)?;
-        let message = NoteOn(*bank, note, velocity);
-        self.send_midi_message(to_software_connection, message)?;
+        if bank != self.state_manager.get_current_bank() {
+            trace!(
+                "Stored feedback for note {} of hidden bank {}",
+                u8::from(note),
+                bank + 1
+            );
+            return Ok(());
+        }
 
         self.led_controller.refresh_single_led(
             to_controller_connection,
             &mut self.state_manager,
             bank,
             note,
-        )?;
+        )
+    }
+
+    fn toggle_note_handler(
+        &mut self,
+        to_controller_connection: &mut OutputConnection,
+        software_outputs: &mut SoftwareOutputs,
+        bank: u8,
+        note: Note,
+        velocity: Velocity,
+    ) -> Result<()> {
+        let previous_state = self.get_note(bank, note)?.state;
+        self.state_manager.toggle_note_state(bank, note)?;
+        self.feedback.expect(bank, u8::from(note), previous_state);
+        let (bank_channel, software_note) = self.mapping_config.get_software_note(bank, note)?;
+        let message = NoteOn(bank_channel, software_note, velocity);
+        software_outputs.send(bank, &message)?;
+
+        if self.get_note(bank, note)?.state {
+            self.switch_off_group(software_outputs, bank, note)?;
+        }
+
+        self.refresh_note_leds(to_controller_connection, bank, note)
+    }
+
+    /// Turns the other notes of the exclusive group off, in the software with a press like the controller
+    fn switch_off_group(
+        &mut self,
+        software_outputs: &mut SoftwareOutputs,
+        bank: u8,
+        note: Note,
+    ) -> Result<()> {
+        let Some(group) = self.mapping_config.get_exclusive_group(note).cloned() else {
+            return Ok(());
+        };
+
+        for other_note in group.into_iter().map(Note::from_u8_lossy) {
+            if other_note == note || !self.get_note(bank, other_note)?.state {
+                continue;
+            }
+
+            self.state_manager.set_note_state(bank, other_note, false)?;
+            self.feedback.expect(bank, u8::from(other_note), true);
+            let (bank_channel, software_note) =
+                self.mapping_config.get_software_note(bank, other_note)?;
+            let message = NoteOn(bank_channel, software_note, Velocity::MAX);
+            software_outputs.send(bank, &message)?;
+        }
 
         Ok(())
     }
 
-    fn forward_raw_message(
-        &self,
-        output_connection: &mut OutputConnection,
-        message: &MidiMessage,
+    /// Repaints the LED of the note, or of its whole exclusive group
+    fn refresh_note_leds(
+        &mut self,
+        to_controller_connection: &mut OutputConnection,
+        bank: u8,
+        note: Note,
+    ) -> Result<()> {
+        match self.mapping_config.get_exclusive_group(note) {
+            Some(group) => self.led_controller.refresh_all_leds(
+                to_controller_connection,
+                &mut self.state_manager,
+                bank,
+                group,
+            ),
+            None => self.led_controller.refresh_single_led(
+                to_controller_connection,
+                &mut self.state_manager,
+                bank,
+                note,
+            ),
+        }
+    }
+
+    /// Press with the velocity, release without
+    fn momentary_note_handler(
+        &mut self,
+        to_controller_connection: &mut OutputConnection,
+        software_outputs: &mut SoftwareOutputs,
+        current_bank: u8,
+        note: Note,
+        press: Option<Velocity>,
     ) -> Result<()> {
-        self.send_midi_message(output_connection, message.clone())
+        let bank = match press {
+            Some(_) => {
+                self.held_notes.insert(u8::from(note), current_bank);
+                current_bank
+            }
+            // The bank may have been switched while the note was held
+            None => self
+                .held_notes
+                .remove(&u8::from(note))
+                .unwrap_or(current_bank),
+        };
+
+        self.state_manager
+            .set_note_state(bank, note, press.is_some())?;
+        let (bank_channel, software_note) = self.mapping_config.get_software_note(bank, note)?;
+        let message = match press {
+            Some(velocity) => NoteOn(bank_channel, software_note, velocity),
+            None => NoteOff(bank_channel, software_note, Velocity::MIN),
+        };
+        software_outputs.send(bank, &message)?;
+
+        self.refresh_visible_led(to_controller_connection, bank, note)
     }
 
-    fn send_midi_message(
+    /// Sends a control value of the software to the knob or fader it was remapped from
+    fn send_control(
         &self,
-        output_connection: &mut OutputConnection,
-        message: MidiMessage,
+        to_controller_connection: &mut OutputConnection,
+        control: u8,
+        value: u8,
     ) -> Result<()> {
-        let self_connection = &mut output_connection.connection;
+        let (channel, controller_control) = self
+            .mapping_config
+            .get_controller_control(control)
+            .unwrap_or_else(|| {
+                let channel = self.state_manager.get_control_channel(control);
+                (channel.unwrap_or(Channel::Ch1), control)
+            });
+
+        let message = ControlChange(
+            channel,
+            ControlFunction(U7::from_u8_lossy(controller_control)),
+            U7::from_u8_lossy(value),
+        );
+
+        to_controller_connection.send(&message)
+    }
+
+    /// Momentary and passthrough notes have no feedback, the software only echoes them
+    fn is_echo(&self, channel: Channel, note: Note) -> bool {
+        let banks = self.state_manager.get_bank_count();
+
+        self.mapping_config.has_bank_note(banks, channel, note)
+    }
+
+    fn get_bank_note(&self, channel: Channel, note: Note) -> Option<(u8, Note)> {
+        let banks = self.state_manager.get_bank_count();
+
+        self.mapping_config.get_bank_note(banks, channel, note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two banks on channel 1 and 2, LEDs are note on with velocity 127 or 0.
+    /// Notes 5 and 6 toggle, note 7 is momentary
+    fn midi_handler() -> MidiHandler {
+        let config: ControllerConfig = toml::from_str(
+            r#"
+            name = "controller"
+            bank_switch = { type = "buttons", next = 100, prev = 101, banks = 2 }
+            led = { profile = "velocity" }
+            maps = { toggle_notes = [5, 6], note_modes = [{ notes = [7], mode = "momentary" }], note_map = [], control_map = [] }
+            "#,
+        )
+        .unwrap();
+
+        MidiHandler::new(&config, &FeedbackConfig::default())
+    }
+
+    fn note_on(channel: Channel, note: u8) -> MidiMessage<'static> {
+        NoteOn(channel, Note::from_u8_lossy(note), Velocity::MAX)
+    }
+
+    #[test]
+    fn feedback_for_hidden_bank_is_only_stored() {
+        let mut handler = midi_handler();
+        let mut connection = OutputConnection::new();
+
+        assert!(
+            handler
+                .handle_software_msg(note_on(Channel::Ch2, 5), &mut connection)
+                .unwrap()
+        );
+
+        assert!(handler.get_note(1, Note::from_u8_lossy(5)).unwrap().state);
+        assert!(!handler.get_note(0, Note::from_u8_lossy(5)).unwrap().state);
+        assert!(connection.sent.is_empty());
+    }
+
+    #[test]
+    fn feedback_for_current_bank_paints_the_led() {
+        let mut handler = midi_handler();
+        let mut connection = OutputConnection::new();
 
-        if let Some(connection) = self_connection {
-            let mut buffer = [0_u8; 3];
-            let length = message.copy_to_slice(&mut buffer)?;
-            connection.send(&buffer[..length])?;
+        handler
+            .handle_software_msg(note_on(Channel::Ch1, 5), &mut connection)
+            .unwrap();
+
+        assert!(handler.get_note(0, Note::from_u8_lossy(5)).unwrap().state);
+        assert_eq!(connection.sent, vec![vec![0x90, 5, 127]]);
+    }
+
+    #[test]
+    fn bank_switch_paints_stored_hidden_bank_feedback() {
+        let mut handler = midi_handler();
+        let mut connection = OutputConnection::new();
+
+        handler
+            .handle_software_msg(note_on(Channel::Ch2, 6), &mut connection)
+            .unwrap();
+        handler.switch_bank(1, &mut connection).unwrap();
+
+        assert_eq!(
+            connection.sent,
+            vec![vec![0x90, 5, 0], vec![0x90, 6, 127], vec![0x90, 7, 0]]
+        );
+    }
+
+    #[test]
+    fn note_on_with_velocity_zero_releases_momentary_note() {
+        let mut handler = midi_handler();
+        let mut connection = OutputConnection::new();
+        let mut software_outputs = SoftwareOutputs::new(&[]);
+
+        for velocity in [127, 0] {
+            let message = NoteOn(
+                Channel::Ch1,
+                Note::from_u8_lossy(7),
+                Velocity::from_u8_lossy(velocity),
+            );
+            handler
+                .handle_controller_msg(message, &mut connection, &mut software_outputs)
+                .unwrap();
         }
 
-        Ok(())
+        assert!(!handler.get_note(0, Note::from_u8_lossy(7)).unwrap().state);
+        assert!(handler.held_notes.is_empty());
+        assert_eq!(connection.sent, vec![vec![0x90, 7, 127], vec![0x90, 7, 0]]);
+    }
+
+    #[test]
+    fn echo_of_momentary_note_is_handled_without_led() {
+        let mut handler = midi_handler();
+        let mut connection = OutputConnection::new();
+
+        assert!(
+            handler
+                .handle_software_msg(note_on(Channel::Ch1, 7), &mut connection)
+                .unwrap()
+        );
+        assert!(
+            !handler
+                .handle_software_msg(note_on(Channel::Ch1, 8), &mut connection)
+                .unwrap()
+        );
+        assert!(connection.sent.is_empty());
     }
 }
@@ -1,7 +1,16 @@
+pub(crate) mod bank_switch;
+pub(crate) mod feedback;
 mod input_connection;
 mod led_controller;
+pub(crate) mod led_profile;
 pub(crate) mod mapping_config;
 pub(crate) mod midi_connection;
 mod midi_handler;
 mod output_connection;
+pub(crate) mod port_match;
+pub(crate) mod retry;
+pub(crate) mod router_handle;
+pub(crate) mod rule_engine;
+mod software_output;
+pub(crate) mod software_route;
 mod state_manager;
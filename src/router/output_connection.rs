@@ -1,21 +1,70 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
+use wmidi::MidiMessage;
 
 pub struct OutputConnection {
     pub connection: Option<MidiOutputConnection>,
+    /// Every message passed to send, so tests can check what reached the port
+    #[cfg(test)]
+    pub sent: Vec<Vec<u8>>,
 }
 
 impl OutputConnection {
     pub fn new() -> Self {
-        Self { connection: None }
+        Self {
+            connection: None,
+            #[cfg(test)]
+            sent: Vec::new(),
+        }
     }
 
     pub fn connect(&mut self, name: &str, midi: MidiOutput, port: &MidiOutputPort) -> Result<()> {
-        let connection = midi.connect(port, name)?;
+        let connection = midi.connect(port, name).map_err(|err| anyhow!("{}", err))?;
 
         let self_connection = &mut self.connection;
         *self_connection = Some(connection);
 
         Ok(())
     }
+
+    /// Creates an own virtual port other applications can connect to
+    #[cfg(unix)]
+    pub fn create_virtual(&mut self, name: &str, midi: MidiOutput) -> Result<()> {
+        use midir::os::unix::VirtualOutput;
+
+        let connection = midi
+            .create_virtual(name)
+            .map_err(|err| anyhow!("{}", err))?;
+
+        let self_connection = &mut self.connection;
+        *self_connection = Some(connection);
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn create_virtual(&mut self, _name: &str, _midi: MidiOutput) -> Result<()> {
+        Err(anyhow!(
+            "Virtual MIDI ports are not supported on this platform"
+        ))
+    }
+
+    pub fn disconnect(&mut self) {
+        let self_connection = &mut self.connection;
+        *self_connection = None;
+    }
+
+    pub fn send(&mut self, message: &MidiMessage) -> Result<()> {
+        let mut buffer = [0_u8; 3];
+        let length = message.copy_to_slice(&mut buffer)?;
+
+        #[cfg(test)]
+        self.sent.push(buffer[..length].to_vec());
+
+        if let Some(connection) = &mut self.connection {
+            connection.send(&buffer[..length])?;
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,280 @@
+use anyhow::{Result, anyhow};
+use log::warn;
+use midir::MidiIO;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PortMatchConfig {
+    #[serde(flatten)]
+    mode: PortMatchMode,
+    /// Use the first available port if nothing matches (not recommended)
+    #[serde(default)]
+    fallback: bool,
+    /// Regex mode patterns by configured name, compiled once when the config is loaded
+    #[serde(skip)]
+    regexes: HashMap<String, Regex>,
+}
+
+impl PartialEq for PortMatchConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.mode == other.mode && self.fallback == other.fallback
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum PortMatchMode {
+    /// Port name equals the configured name (case-insensitive)
+    Exact,
+    /// Port name contains the configured name (case-insensitive)
+    #[default]
+    Substring,
+    /// Configured name is a regular expression (case-insensitive).
+    /// For software ports it is matched against the part of the port name after from_/to_
+    Regex,
+    /// Fixed port indices, as listed in the error and by --list-ports
+    Index {
+        controller_input: usize,
+        controller_output: usize,
+        software_input: usize,
+        software_output: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortRole {
+    ControllerInput,
+    ControllerOutput,
+    SoftwareInput,
+    SoftwareOutput,
+}
+
+impl fmt::Display for PortRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PortRole::ControllerInput => "controller input",
+            PortRole::ControllerOutput => "controller output",
+            PortRole::SoftwareInput => "software input",
+            PortRole::SoftwareOutput => "software output",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl PortRole {
+    /// Software ports are named from_<software> and to_<software>
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            PortRole::ControllerInput | PortRole::ControllerOutput => "",
+            PortRole::SoftwareInput => "from_",
+            PortRole::SoftwareOutput => "to_",
+        }
+    }
+}
+
+impl PortMatchConfig {
+    pub fn validate(&self, controllers: usize) -> Result<()> {
+        if matches!(self.mode, PortMatchMode::Index { .. }) && controllers > 1 {
+            return Err(anyhow!(
+                "Port match mode index only supports one controller"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the controller and software names in regex mode, fails on invalid patterns
+    pub fn compile(&mut self, names: &[&str]) -> Result<()> {
+        self.regexes.clear();
+
+        if self.mode == PortMatchMode::Regex {
+            for name in names {
+                self.regexes.insert(name.to_string(), build_regex(name)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_match(
+        &self,
+        port_name: &str,
+        index: usize,
+        name: &str,
+        role: PortRole,
+    ) -> Result<bool> {
+        let full_name = format!("{}{}", role.prefix(), name).to_lowercase();
+
+        Ok(match &self.mode {
+            PortMatchMode::Exact => port_name.to_lowercase() == full_name,
+            PortMatchMode::Substring => port_name.to_lowercase().contains(&full_name),
+            PortMatchMode::Regex => {
+                // The prefix is matched outside the regex, so ^ anchors to the software name
+                let Some(start) = port_name.to_ascii_lowercase().find(role.prefix()) else {
+                    return Ok(false);
+                };
+                let port_name = &port_name[start + role.prefix().len()..];
+
+                match self.regexes.get(name) {
+                    Some(regex) => regex.is_match(port_name),
+                    None => build_regex(name)?.is_match(port_name),
+                }
+            }
+            PortMatchMode::Index {
+                controller_input,
+                controller_output,
+                software_input,
+                software_output,
+            } => {
+                index
+                    == match role {
+                        PortRole::ControllerInput => *controller_input,
+                        PortRole::ControllerOutput => *controller_output,
+                        PortRole::SoftwareInput => *software_input,
+                        PortRole::SoftwareOutput => *software_output,
+                    }
+            }
+        })
+    }
+
+    /// Finds the port for the name, the error lists all candidates if nothing matches
+    pub fn find_port<P: MidiIO>(
+        &self,
+        midi_io: &P,
+        ports: &[P::Port],
+        name: &str,
+        role: PortRole,
+    ) -> Result<P::Port> {
+        let full_name = format!("{}{}", role.prefix(), name);
+
+        if ports.is_empty() {
+            return Err(anyhow!("No MIDI ports available for '{}'", full_name));
+        }
+
+        if let Some(index) = self.position(midi_io, ports, name, role)? {
+            return Ok(ports[index].clone());
+        }
+
+        if self.fallback {
+            warn!(
+                "No matching port for '{}', using first available.",
+                full_name
+            );
+            return Ok(ports[0].clone());
+        }
+
+        let candidates = ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| {
+                format!(
+                    "[{}] {}",
+                    index,
+                    midi_io.port_name(port).unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Err(anyhow!(
+            "No {} port matches '{}' ({}), available ports: {}",
+            role,
+            full_name,
+            self.mode_name(),
+            candidates
+        ))
+    }
+
+    /// Whether find_port would connect to a port, so a fallback connection doesn't count as unplugged
+    pub fn has_port<P: MidiIO>(
+        &self,
+        midi_io: &P,
+        ports: &[P::Port],
+        name: &str,
+        role: PortRole,
+    ) -> bool {
+        self.get_port_index(midi_io, ports, name, role).is_some()
+    }
+
+    /// Index of the port find_port would use, including the fallback
+    pub fn get_port_index<P: MidiIO>(
+        &self,
+        midi_io: &P,
+        ports: &[P::Port],
+        name: &str,
+        role: PortRole,
+    ) -> Option<usize> {
+        match self.position(midi_io, ports, name, role) {
+            Ok(Some(index)) => Some(index),
+            Ok(None) if self.fallback && !ports.is_empty() => Some(0),
+            _ => None,
+        }
+    }
+
+    fn position<P: MidiIO>(
+        &self,
+        midi_io: &P,
+        ports: &[P::Port],
+        name: &str,
+        role: PortRole,
+    ) -> Result<Option<usize>> {
+        for (index, port) in ports.iter().enumerate() {
+            let port_name = midi_io.port_name(port).unwrap_or_default();
+
+            if self.is_match(&port_name, index, name, role)? {
+                return Ok(Some(index));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn mode_name(&self) -> &'static str {
+        match self.mode {
+            PortMatchMode::Exact => "exact",
+            PortMatchMode::Substring => "substring",
+            PortMatchMode::Regex => "regex",
+            PortMatchMode::Index { .. } => "index",
+        }
+    }
+}
+
+fn build_regex(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| anyhow!("Invalid port regex '{}': {}", pattern, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex_config(names: &[&str]) -> PortMatchConfig {
+        let mut config: PortMatchConfig = toml::from_str("mode = \"regex\"").unwrap();
+        config.compile(names).unwrap();
+        config
+    }
+
+    #[test]
+    fn software_regex_is_matched_after_the_prefix() {
+        let config = regex_config(&["^Daslight"]);
+        let is_match = |port_name, role| config.is_match(port_name, 0, "^Daslight", role).unwrap();
+
+        assert!(is_match("from_Daslight 1", PortRole::SoftwareInput));
+        assert!(is_match("to_daslight", PortRole::SoftwareOutput));
+        assert!(!is_match("to_Daslight", PortRole::SoftwareInput));
+        assert!(!is_match("from_Old Daslight", PortRole::SoftwareInput));
+        assert!(!is_match("from_Daslight", PortRole::ControllerInput));
+    }
+
+    #[test]
+    fn invalid_regex_fails_to_compile() {
+        let mut config: PortMatchConfig = toml::from_str("mode = \"regex\"").unwrap();
+
+        assert!(config.compile(&["APC40 (mkII"]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Exponential backoff for connection attempts
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Number of retries, 0 retries forever
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default = "default_initial_delay_ms")]
+    initial_delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")]
+    max_delay_ms: u64,
+    #[serde(default = "default_backoff_factor")]
+    backoff_factor: u32,
+}
+
+fn default_initial_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_ms() -> u64 {
+    30000
+}
+
+fn default_backoff_factor() -> u32 {
+    2
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            initial_delay_ms: default_initial_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            backoff_factor: default_backoff_factor(),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.initial_delay_ms == 0 || self.initial_delay_ms > self.max_delay_ms {
+            return Err(anyhow!(
+                "Retry delays are out of range (0 < initial_delay_ms <= max_delay_ms)"
+            ));
+        }
+
+        if self.backoff_factor == 0 {
+            return Err(anyhow!("Retry backoff_factor must be at least 1"));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Whether another retry is allowed after the given number of failed attempts
+    pub fn should_retry(&self, failed: u32) -> bool {
+        self.attempts == 0 || failed <= self.attempts
+    }
+
+    /// Delay before the retry after the given number of failed attempts
+    pub fn get_delay(&self, failed: u32) -> Duration {
+        let factor = (self.backoff_factor as u64).saturating_pow(failed.saturating_sub(1));
+        let delay = self.initial_delay_ms.saturating_mul(factor);
+
+        Duration::from_millis(delay.min(self.max_delay_ms))
+    }
+}
@@ -0,0 +1,374 @@
+use crate::{
+    router::{
+        feedback::FeedbackConfig, midi_handler::MidiHandler, output_connection::OutputConnection,
+        software_output::SoftwareOutputs, state_manager::NoteState,
+    },
+    utils::config::{Config, ControllerConfig, RouterConfig},
+};
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use midir::{MidiOutput, MidiOutputPort};
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use wmidi::{
+    MidiMessage,
+    MidiMessage::{NoteOff, NoteOn},
+    Note, Velocity,
+};
+
+/// Long-lived handle to the router state and its outputs, shared by the
+/// MIDI callbacks and the REST api. It survives router restarts.
+#[derive(Clone)]
+pub(crate) struct RouterHandle {
+    controllers: Arc<Mutex<Vec<Controller>>>,
+    software_outputs: Arc<Mutex<SoftwareOutputs>>,
+    router_config: Arc<Mutex<RouterConfig>>,
+    config_path: PathBuf,
+    status: Arc<Mutex<RouterStatus>>,
+    /// Only one thread writes the state files at a time
+    save_lock: Arc<Mutex<()>>,
+    restart: Arc<AtomicBool>,
+}
+
+/// Bank state, maps and LED output of one controller
+struct Controller {
+    handler: MidiHandler,
+    connection: OutputConnection,
+}
+
+/// Connection state of the router, shown in the TUI and the REST api
+#[derive(Debug, Clone)]
+pub(crate) enum RouterStatus {
+    Connecting,
+    Connected,
+    /// Connecting failed, the next attempt follows after the retry delay
+    Waiting {
+        device: String,
+        attempt: u32,
+        error: String,
+    },
+    /// A port disappeared, the router reconnects once it is back
+    Disconnected,
+    Failed(String),
+}
+
+impl Controller {
+    fn new(config: &ControllerConfig, feedback: &FeedbackConfig) -> Self {
+        Self {
+            handler: MidiHandler::new(config, feedback),
+            connection: OutputConnection::new(),
+        }
+    }
+}
+
+impl RouterHandle {
+    pub fn new(config: &Config, config_path: PathBuf, restart: Arc<AtomicBool>) -> Self {
+        Self {
+            controllers: Arc::new(Mutex::new(
+                config
+                    .router
+                    .controllers
+                    .iter()
+                    .map(|controller| Controller::new(controller, &config.router.feedback))
+                    .collect(),
+            )),
+            software_outputs: Arc::new(Mutex::new(SoftwareOutputs::new(&config.router.software))),
+            router_config: Arc::new(Mutex::new(config.router.clone())),
+            config_path,
+            status: Arc::new(Mutex::new(RouterStatus::Connecting)),
+            save_lock: Arc::new(Mutex::new(())),
+            restart,
+        }
+    }
+
+    pub fn get_router_config(&self) -> RouterConfig {
+        self.router_config.lock().unwrap().clone()
+    }
+
+    pub fn get_config_path(&self) -> PathBuf {
+        self.config_path.clone()
+    }
+
+    pub fn get_status(&self) -> RouterStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn set_status(&self, status: RouterStatus) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    /// Reads the config file again and swaps the new maps into the running router.
+    /// The toggle states are kept, a reconnect is requested if the port names changed.
+    pub fn reload_config(&self) -> Result<()> {
+        let config = Config::new(&self.config_path)?;
+        let old_config = self.get_router_config();
+
+        // Other controllers get new handlers, their states are kept in the state files
+        let rebuild = old_config.get_controller_names() != config.router.get_controller_names();
+        let software_changed =
+            old_config.get_software_names() != config.router.get_software_names();
+        let reconnect = rebuild
+            || software_changed
+            || old_config.virtual_ports != config.router.virtual_ports
+            || old_config.port_match != config.router.port_match;
+
+        if rebuild {
+            self.save_state();
+        }
+
+        *self.router_config.lock().unwrap() = config.router.clone();
+
+        {
+            let mut controllers_lock = self.controllers.lock().unwrap();
+
+            if rebuild {
+                *controllers_lock = config
+                    .router
+                    .controllers
+                    .iter()
+                    .map(|controller| Controller::new(controller, &config.router.feedback))
+                    .collect();
+            } else {
+                for (controller, controller_config) in
+                    controllers_lock.iter_mut().zip(&config.router.controllers)
+                {
+                    controller
+                        .handler
+                        .set_config(controller_config, &config.router.feedback);
+                }
+            }
+
+            // Locked after the controllers like everywhere else
+            let mut software_lock = self.software_outputs.lock().unwrap();
+
+            if software_changed {
+                *software_lock = SoftwareOutputs::new(&config.router.software);
+            } else {
+                software_lock.set_routes(&config.router.software);
+            }
+        }
+
+        if rebuild {
+            self.restore_state();
+        }
+
+        info!("Reloaded config");
+
+        if reconnect {
+            info!("Ports changed, reconnecting...");
+            self.restart.store(true, Ordering::SeqCst);
+        } else {
+            self.refresh_leds()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn restore_state(&self) {
+        let router_config = self.get_router_config();
+        let mut controllers_lock = self.controllers.lock().unwrap();
+
+        for (controller, controller_config) in
+            controllers_lock.iter_mut().zip(&router_config.controllers)
+        {
+            let state_file = &controller_config.state_file;
+
+            if !state_file.exists() {
+                continue;
+            }
+
+            match controller.handler.get_state_manager_mut().load(state_file) {
+                Ok(_) => info!("Restored state from {}", state_file.display()),
+                Err(err) => warn!(
+                    "Failed to restore state from {}: {}",
+                    state_file.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    /// Copies the changed states under the lock and writes them without it,
+    /// so the MIDI callbacks don't wait for the disk
+    pub fn save_state(&self) {
+        let _save_lock = self.save_lock.lock().unwrap();
+        let router_config = self.get_router_config();
+        let snapshots = self
+            .controllers
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|controller| controller.handler.get_state_manager_mut().take_snapshot())
+            .collect::<Vec<_>>();
+
+        for (index, (snapshot, controller_config)) in snapshots
+            .into_iter()
+            .zip(&router_config.controllers)
+            .enumerate()
+        {
+            let Some(snapshot) = snapshot else {
+                continue;
+            };
+            let state_file = &controller_config.state_file;
+
+            if let Err(err) = snapshot.save(state_file) {
+                warn!("Failed to save state to {}: {}", state_file.display(), err);
+
+                if let Some(controller) = self.controllers.lock().unwrap().get_mut(index) {
+                    controller.handler.get_state_manager_mut().mark_changed();
+                }
+            }
+        }
+    }
+
+    pub fn refresh_leds(&self) -> Result<()> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+
+        for controller in controllers_lock.iter_mut() {
+            controller
+                .handler
+                .refresh_leds(&mut controller.connection)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn check_feedback(&self) -> Result<()> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+
+        for controller in controllers_lock.iter_mut() {
+            controller
+                .handler
+                .check_feedback(&mut controller.connection)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn connect_to_controller(
+        &self,
+        controller: usize,
+        name: &str,
+        midi: MidiOutput,
+        port: &MidiOutputPort,
+    ) -> Result<()> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let controller = get_controller(&mut controllers_lock, controller)?;
+
+        controller.connection.connect(name, midi, port)
+    }
+
+    pub fn connect_to_software(
+        &self,
+        software: usize,
+        name: &str,
+        midi: MidiOutput,
+        port: &MidiOutputPort,
+    ) -> Result<()> {
+        let mut software_lock = self.software_outputs.lock().unwrap();
+        software_lock.connect(software, name, midi, port)
+    }
+
+    pub fn create_virtual_software(
+        &self,
+        software: usize,
+        name: &str,
+        midi: MidiOutput,
+    ) -> Result<()> {
+        let mut software_lock = self.software_outputs.lock().unwrap();
+        software_lock.create_virtual(software, name, midi)
+    }
+
+    pub fn disconnect(&self) {
+        for controller in self.controllers.lock().unwrap().iter_mut() {
+            controller.connection.disconnect();
+        }
+
+        self.software_outputs.lock().unwrap().disconnect();
+    }
+
+    pub fn handle_controller_msg(&self, controller: usize, msg: MidiMessage) -> Result<()> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let mut software_lock = self.software_outputs.lock().unwrap();
+        let controller = get_controller(&mut controllers_lock, controller)?;
+
+        controller.handler.handle_controller_msg(
+            msg,
+            &mut controller.connection,
+            &mut software_lock,
+        )
+    }
+
+    /// Feedback of all software targets goes to every controller that has the note in one of its banks
+    pub fn handle_software_msg(&self, msg: MidiMessage) -> Result<()> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let mut handled = false;
+
+        for controller in controllers_lock.iter_mut() {
+            handled |= controller
+                .handler
+                .handle_software_msg(msg.clone(), &mut controller.connection)?;
+        }
+
+        if !handled {
+            if let NoteOn(_, note, _) | NoteOff(_, note, _) = msg {
+                warn!("Maps don't include note (Software): {}", u8::from(note));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_current_bank(&self, controller: usize) -> Result<u8> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let controller = get_controller(&mut controllers_lock, controller)?;
+
+        Ok(controller.handler.get_state_manager().get_current_bank())
+    }
+
+    pub fn get_bank_notes(&self, controller: usize, bank: u8) -> Result<Vec<NoteState>> {
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let controller = get_controller(&mut controllers_lock, controller)?;
+
+        controller.handler.get_bank_notes(bank)
+    }
+
+    pub fn set_note(
+        &self,
+        controller: usize,
+        bank: u8,
+        note: u8,
+        state: Option<bool>,
+        color: Option<u8>,
+    ) -> Result<NoteState> {
+        let note = Note::try_from(note).map_err(|_| anyhow!("Invalid note: {}", note))?;
+        let color = color
+            .map(|color| Velocity::try_from(color).map_err(|_| anyhow!("Invalid color: {}", color)))
+            .transpose()?;
+
+        // Same lock order as the MIDI input callbacks to avoid deadlocks
+        let mut controllers_lock = self.controllers.lock().unwrap();
+        let mut software_lock = self.software_outputs.lock().unwrap();
+        let controller = get_controller(&mut controllers_lock, controller)?;
+
+        controller.handler.set_note(
+            bank,
+            note,
+            state,
+            color,
+            &mut controller.connection,
+            &mut software_lock,
+        )
+    }
+}
+
+fn get_controller(controllers: &mut [Controller], index: usize) -> Result<&mut Controller> {
+    controllers
+        .get_mut(index)
+        .ok_or_else(|| anyhow!("Controller {} doesn't exist", index))
+}
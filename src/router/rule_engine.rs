@@ -0,0 +1,495 @@
+use crate::router::software_route::MessageType;
+use anyhow::{Result, anyhow};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use wmidi::{
+    Channel, ControlFunction, MidiMessage,
+    MidiMessage::{
+        ChannelPressure, ControlChange, NoteOff, NoteOn, PitchBendChange, PolyphonicKeyPressure,
+        ProgramChange,
+    },
+    Note, U7,
+};
+
+/// Runs the rules of a controller on its messages, the first matching rule handles a message.
+/// Messages without a matching rule go to the toggle notes and maps.
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Rule {
+    #[serde(rename = "match", default)]
+    filter: RuleMatch,
+    actions: Vec<RuleAction>,
+}
+
+/// All fields that are set must match
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+struct RuleMatch {
+    message: Option<MessageType>,
+    /// MIDI channel (1-16)
+    channel: Option<u8>,
+    /// First and last note, control or program number
+    number: Option<[u8; 2]>,
+    /// Lowest and highest velocity or control value
+    value: Option<[u8; 2]>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum RuleAction {
+    /// Sets the channel and/or number
+    Remap {
+        channel: Option<u8>,
+        number: Option<u8>,
+    },
+    /// Shifts the number, messages moved out of range are dropped
+    Transpose {
+        offset: i8,
+    },
+    /// Scales the value from 0-127 into min-max
+    Scale {
+        min: u8,
+        max: u8,
+    },
+    Drop,
+    /// Sends a copy with another channel and/or number to the software
+    Duplicate {
+        channel: Option<u8>,
+        number: Option<u8>,
+    },
+    /// Sends the message only to this software, its routes are ignored
+    SendToPort {
+        software: String,
+    },
+    SetBank {
+        bank: u8,
+    },
+    /// Hands the message to the toggle notes and maps
+    Default,
+}
+
+/// What the handler does with a message after the rules
+#[derive(Debug)]
+pub enum RuleOutput<'a> {
+    /// Handled by the toggle notes and maps
+    Default(MidiMessage<'a>),
+    /// Sent to the software targets with matching routes
+    Software(MidiMessage<'a>),
+    /// Sent to the software with this name
+    Port(String, MidiMessage<'a>),
+    SetBank(u8),
+}
+
+impl RuleEngine {
+    pub fn new(rules: &[Rule]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+        }
+    }
+
+    /// Returns None if no rule matches the message
+    pub fn apply<'a>(&self, message: &MidiMessage<'a>) -> Option<Vec<RuleOutput<'a>>> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.filter.is_match(message))?;
+        let mut outputs = Vec::new();
+        let mut current = Some(message.clone());
+
+        // Drop, send_to_port and default take the message, later actions are skipped
+        for action in &rule.actions {
+            let Some(message) = current.take() else {
+                break;
+            };
+
+            match action {
+                RuleAction::Remap { channel, number } => {
+                    current = Some(set_fields(&message, *channel, *number, None));
+                }
+                RuleAction::Transpose { offset } => {
+                    current = transpose(&message, *offset);
+
+                    if current.is_none() {
+                        debug!("Rule dropped {:?}, transposed out of range", message);
+                    }
+                }
+                RuleAction::Scale { min, max } => {
+                    let value = get_value(&message).map(|value| {
+                        let range = i16::from(*max) - i16::from(*min);
+                        (i16::from(*min) + i16::from(value) * range / 127) as u8
+                    });
+                    current = Some(set_fields(&message, None, None, value));
+                }
+                RuleAction::Drop => {}
+                RuleAction::Duplicate { channel, number } => {
+                    outputs.push(RuleOutput::Software(set_fields(
+                        &message, *channel, *number, None,
+                    )));
+                    current = Some(message);
+                }
+                RuleAction::SendToPort { software } => {
+                    outputs.push(RuleOutput::Port(software.clone(), message));
+                }
+                RuleAction::SetBank { bank } => {
+                    outputs.push(RuleOutput::SetBank(*bank));
+                    current = Some(message);
+                }
+                RuleAction::Default => {
+                    outputs.push(RuleOutput::Default(message));
+                }
+            }
+        }
+
+        if let Some(message) = current {
+            outputs.push(RuleOutput::Software(message));
+        }
+
+        Some(outputs)
+    }
+}
+
+impl Rule {
+    pub fn validate(&self, banks: u8, software_names: &[&str]) -> Result<()> {
+        self.filter.validate()?;
+
+        if self.actions.is_empty() {
+            return Err(anyhow!(
+                "Rule without actions, use action = \"drop\" to drop messages"
+            ));
+        }
+
+        for action in &self.actions {
+            match action {
+                RuleAction::Remap { channel, number }
+                | RuleAction::Duplicate { channel, number } => {
+                    validate_channel(*channel)?;
+                    validate_u7("number", number.iter())?;
+                }
+                RuleAction::Scale { min, max } => validate_u7("scale", [min, max].into_iter())?,
+                RuleAction::SendToPort { software } => {
+                    if !software_names.contains(&software.as_str()) {
+                        return Err(anyhow!("Rule sends to unknown software {}", software));
+                    }
+                }
+                RuleAction::SetBank { bank } => {
+                    if *bank >= banks {
+                        return Err(anyhow!(
+                            "Rule switches to bank {}, but there are only {} banks",
+                            bank,
+                            banks
+                        ));
+                    }
+                }
+                RuleAction::Transpose { .. } | RuleAction::Drop | RuleAction::Default => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RuleMatch {
+    fn validate(&self) -> Result<()> {
+        validate_channel(self.channel)?;
+
+        for (field, range) in [("number", self.number), ("value", self.value)] {
+            if let Some([first, last]) = range {
+                if first > last || last > 127 {
+                    return Err(anyhow!(
+                        "Rule {} [{}, {}] is out of range (0-127, first <= last)",
+                        field,
+                        first,
+                        last
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_match(&self, message: &MidiMessage) -> bool {
+        if let Some(message_type) = self.message {
+            if MessageType::of(message) != message_type {
+                return false;
+            }
+        }
+
+        if let Some(channel) = self.channel {
+            if message.channel().map(|channel| channel.index() + 1) != Some(channel) {
+                return false;
+            }
+        }
+
+        let in_range = |range: Option<[u8; 2]>, field: Option<u8>| match (range, field) {
+            (Some([first, last]), Some(field)) => (first..=last).contains(&field),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        in_range(self.number, get_number(message)) && in_range(self.value, get_value(message))
+    }
+}
+
+fn validate_channel(channel: Option<u8>) -> Result<()> {
+    match channel {
+        Some(channel) if !(1..=16).contains(&channel) => {
+            Err(anyhow!("Rule channel {} is out of range (1-16)", channel))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_u7<'a>(field: &str, mut values: impl Iterator<Item = &'a u8>) -> Result<()> {
+    match values.find(|value| **value > 127) {
+        Some(value) => Err(anyhow!("Rule {} {} is out of range (0-127)", field, value)),
+        None => Ok(()),
+    }
+}
+
+fn transpose<'a>(message: &MidiMessage<'a>, offset: i8) -> Option<MidiMessage<'a>> {
+    let Some(number) = get_number(message) else {
+        return Some(message.clone());
+    };
+
+    let number = u8::try_from(i16::from(number) + i16::from(offset))
+        .ok()
+        .filter(|number| *number <= 127)?;
+
+    Some(set_fields(message, None, Some(number), None))
+}
+
+/// Note, control or program number
+fn get_number(message: &MidiMessage) -> Option<u8> {
+    match message {
+        NoteOn(_, note, _) | NoteOff(_, note, _) | PolyphonicKeyPressure(_, note, _) => {
+            Some(u8::from(*note))
+        }
+        ControlChange(_, control, _) => Some(u8::from(*control)),
+        ProgramChange(_, program) => Some(u8::from(*program)),
+        _ => None,
+    }
+}
+
+/// Velocity, pressure or control value
+fn get_value(message: &MidiMessage) -> Option<u8> {
+    match message {
+        NoteOn(_, _, value)
+        | NoteOff(_, _, value)
+        | PolyphonicKeyPressure(_, _, value)
+        | ControlChange(_, _, value)
+        | ChannelPressure(_, value) => Some(u8::from(*value)),
+        _ => None,
+    }
+}
+
+/// Replaces the fields that are set and the message has, the values are validated by the config
+fn set_fields<'a>(
+    message: &MidiMessage<'a>,
+    channel: Option<u8>,
+    number: Option<u8>,
+    value: Option<u8>,
+) -> MidiMessage<'a> {
+    let channel = |old: &Channel| {
+        channel
+            .and_then(|channel| Channel::from_index(channel - 1).ok())
+            .unwrap_or(*old)
+    };
+    let note = |old: &Note| number.map_or(*old, Note::from_u8_lossy);
+    let u7 = |old: &U7, new: Option<u8>| new.map_or(*old, U7::from_u8_lossy);
+
+    match message {
+        NoteOn(c, n, v) => NoteOn(channel(c), note(n), u7(v, value)),
+        NoteOff(c, n, v) => NoteOff(channel(c), note(n), u7(v, value)),
+        PolyphonicKeyPressure(c, n, v) => PolyphonicKeyPressure(channel(c), note(n), u7(v, value)),
+        ControlChange(c, f, v) => {
+            ControlChange(channel(c), ControlFunction(u7(&f.0, number)), u7(v, value))
+        }
+        ProgramChange(c, p) => ProgramChange(channel(c), u7(p, number)),
+        ChannelPressure(c, v) => ChannelPressure(channel(c), u7(v, value)),
+        PitchBendChange(c, b) => PitchBendChange(channel(c), *b),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_engine(rules: &str) -> RuleEngine {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<Rule>,
+        }
+
+        let rules: Rules = toml::from_str(rules).unwrap();
+        RuleEngine::new(&rules.rules)
+    }
+
+    fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage<'static> {
+        NoteOn(
+            Channel::from_index(channel - 1).unwrap(),
+            Note::from_u8_lossy(note),
+            U7::from_u8_lossy(velocity),
+        )
+    }
+
+    fn control(channel: u8, control: u8, value: u8) -> MidiMessage<'static> {
+        ControlChange(
+            Channel::from_index(channel - 1).unwrap(),
+            ControlFunction(U7::from_u8_lossy(control)),
+            U7::from_u8_lossy(value),
+        )
+    }
+
+    /// Outputs as strings, so the tables can compare them
+    fn apply(engine: &RuleEngine, message: &MidiMessage) -> Option<Vec<String>> {
+        engine.apply(message).map(|outputs| {
+            outputs
+                .iter()
+                .map(|output| format!("{:?}", output))
+                .collect()
+        })
+    }
+
+    fn software(message: MidiMessage) -> String {
+        format!("{:?}", RuleOutput::Software(message))
+    }
+
+    #[test]
+    fn transpose_drops_out_of_range_numbers() {
+        let cases = [
+            (12, note_on(1, 60, 100), Some(note_on(1, 72, 100))),
+            (-12, note_on(1, 60, 100), Some(note_on(1, 48, 100))),
+            (10, note_on(1, 117, 100), Some(note_on(1, 127, 100))),
+            (11, note_on(1, 117, 100), None),
+            (-1, note_on(1, 0, 100), None),
+            (-128, note_on(1, 127, 100), None),
+            (127, control(1, 1, 64), None),
+        ];
+
+        for (offset, message, expected) in cases {
+            let engine = rule_engine(&format!(
+                "[[rules]]\nactions = [{{ action = \"transpose\", offset = {} }}]",
+                offset
+            ));
+
+            assert_eq!(
+                apply(&engine, &message),
+                Some(expected.into_iter().map(software).collect()),
+                "offset {} on {:?}",
+                offset,
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn scale_maps_values_into_the_range() {
+        let cases = [
+            (0, 127, 0, 0),
+            (0, 127, 127, 127),
+            (0, 127, 64, 64),
+            (20, 100, 0, 20),
+            (20, 100, 127, 100),
+            (20, 100, 64, 60),
+            (100, 20, 0, 100),
+            (100, 20, 127, 20),
+            (0, 0, 127, 0),
+        ];
+
+        for (min, max, value, expected) in cases {
+            let engine = rule_engine(&format!(
+                "[[rules]]\nactions = [{{ action = \"scale\", min = {}, max = {} }}]",
+                min, max
+            ));
+
+            assert_eq!(
+                apply(&engine, &control(1, 7, value)),
+                Some(vec![software(control(1, 7, expected))]),
+                "scale {} into {}-{}",
+                value,
+                min,
+                max
+            );
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_handles_the_message() {
+        let engine = rule_engine(
+            r#"
+            [[rules]]
+            match = { message = "note", number = [0, 15] }
+            actions = [{ action = "remap", number = 1 }]
+
+            [[rules]]
+            match = { message = "note", channel = 2 }
+            actions = [{ action = "remap", number = 2 }]
+
+            [[rules]]
+            match = { message = "note" }
+            actions = [{ action = "remap", number = 3 }]
+            "#,
+        );
+
+        let cases = [
+            (note_on(2, 10, 100), Some(note_on(2, 1, 100))),
+            (note_on(2, 20, 100), Some(note_on(2, 2, 100))),
+            (note_on(1, 20, 100), Some(note_on(1, 3, 100))),
+            (control(2, 10, 100), None),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(
+                apply(&engine, &message),
+                expected.map(|expected| vec![software(expected)]),
+                "{:?}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn actions_end_or_continue_the_chain() {
+        let message = note_on(1, 60, 100);
+        let remapped = note_on(1, 61, 100);
+        let cases = [
+            ("{ action = \"drop\" }", vec![]),
+            (
+                "{ action = \"default\" }",
+                vec![format!("{:?}", RuleOutput::Default(message.clone()))],
+            ),
+            (
+                "{ action = \"send_to_port\", software = \"Daslight\" }",
+                vec![format!(
+                    "{:?}",
+                    RuleOutput::Port("Daslight".to_string(), message.clone())
+                )],
+            ),
+            (
+                "{ action = \"duplicate\", channel = 2 }",
+                vec![software(note_on(2, 60, 100)), software(remapped.clone())],
+            ),
+            (
+                "{ action = \"set_bank\", bank = 1 }",
+                vec![
+                    format!("{:?}", RuleOutput::SetBank(1)),
+                    software(remapped.clone()),
+                ],
+            ),
+        ];
+
+        // The remap after the tested action only shows up if the chain continues
+        for (action, expected) in cases {
+            let engine = rule_engine(&format!(
+                "[[rules]]\nactions = [{}, {{ action = \"remap\", number = 61 }}]",
+                action
+            ));
+
+            assert_eq!(apply(&engine, &message), Some(expected), "{}", action);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    router::{output_connection::OutputConnection, software_route::SoftwareRoute},
+    utils::config::SoftwareConfig,
+};
+use anyhow::{Result, anyhow};
+use midir::{MidiOutput, MidiOutputPort};
+use wmidi::MidiMessage;
+
+/// Outputs to all software targets, every message goes to the targets whose routes match
+pub struct SoftwareOutputs {
+    targets: Vec<SoftwareOutput>,
+}
+
+struct SoftwareOutput {
+    name: String,
+    routes: Vec<SoftwareRoute>,
+    connection: OutputConnection,
+}
+
+impl SoftwareOutputs {
+    pub fn new(configs: &[SoftwareConfig]) -> Self {
+        Self {
+            targets: configs
+                .iter()
+                .map(|config| SoftwareOutput {
+                    name: config.name.clone(),
+                    routes: config.routes.clone(),
+                    connection: OutputConnection::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Swaps in reloaded routes, the connections are kept
+    pub fn set_routes(&mut self, configs: &[SoftwareConfig]) {
+        for (target, config) in self.targets.iter_mut().zip(configs) {
+            target.routes = config.routes.clone();
+        }
+    }
+
+    pub fn connect(
+        &mut self,
+        software: usize,
+        name: &str,
+        midi: MidiOutput,
+        port: &MidiOutputPort,
+    ) -> Result<()> {
+        self.get_target(software)?
+            .connection
+            .connect(name, midi, port)
+    }
+
+    pub fn create_virtual(&mut self, software: usize, name: &str, midi: MidiOutput) -> Result<()> {
+        self.get_target(software)?
+            .connection
+            .create_virtual(name, midi)
+    }
+
+    pub fn disconnect(&mut self) {
+        for target in &mut self.targets {
+            target.connection.disconnect();
+        }
+    }
+
+    /// Sends the message of a bank to every target with a matching route,
+    /// targets without routes receive everything
+    pub fn send(&mut self, bank: u8, message: &MidiMessage) -> Result<()> {
+        for target in &mut self.targets {
+            let is_routed = target.routes.is_empty()
+                || target
+                    .routes
+                    .iter()
+                    .any(|route| route.is_match(bank, message));
+
+            if is_routed {
+                target.connection.send(message)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the message only to the software with this name, its routes are ignored
+    pub fn send_to(&mut self, name: &str, message: &MidiMessage) -> Result<()> {
+        let target = self
+            .targets
+            .iter_mut()
+            .find(|target| target.name == name)
+            .ok_or_else(|| anyhow!("Software {} doesn't exist", name))?;
+
+        target.connection.send(message)
+    }
+
+    fn get_target(&mut self, software: usize) -> Result<&mut SoftwareOutput> {
+        self.targets
+            .get_mut(software)
+            .ok_or_else(|| anyhow!("Software {} doesn't exist", software))
+    }
+}
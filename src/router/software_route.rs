@@ -0,0 +1,109 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use wmidi::{
+    MidiMessage,
+    MidiMessage::{ControlChange, NoteOff, NoteOn, PolyphonicKeyPressure, ProgramChange},
+};
+
+/// Filter for the messages a software receives, all fields that are set must match
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SoftwareRoute {
+    /// Banks of the controller, numbered from 0
+    banks: Option<Vec<u8>>,
+    /// MIDI channels (1-16) the message is sent on
+    channels: Option<Vec<u8>>,
+    /// First and last note, only checked for note messages
+    notes: Option<[u8; 2]>,
+    messages: Option<Vec<MessageType>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MessageType {
+    Note,
+    ControlChange,
+    ProgramChange,
+    Other,
+}
+
+impl MessageType {
+    pub fn of(message: &MidiMessage) -> Self {
+        match message {
+            NoteOn(..) | NoteOff(..) | PolyphonicKeyPressure(..) => Self::Note,
+            ControlChange(..) => Self::ControlChange,
+            ProgramChange(..) => Self::ProgramChange,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl SoftwareRoute {
+    /// Banks are checked against the controller with the most banks
+    pub fn validate(&self, banks: u8) -> Result<()> {
+        if let Some(bank) = self.banks.iter().flatten().find(|bank| **bank >= banks) {
+            return Err(anyhow!(
+                "Route bank {} is out of range, there are only {} banks",
+                bank,
+                banks
+            ));
+        }
+
+        if let Some(channel) = self
+            .channels
+            .iter()
+            .flatten()
+            .find(|channel| !(1..=16).contains(*channel))
+        {
+            return Err(anyhow!("Route channel {} is out of range (1-16)", channel));
+        }
+
+        if let Some([first, last]) = self.notes {
+            if first > last || last > 127 {
+                return Err(anyhow!(
+                    "Route notes [{}, {}] are out of range (0-127, first <= last)",
+                    first,
+                    last
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a message of the bank passes this route
+    pub fn is_match(&self, bank: u8, message: &MidiMessage) -> bool {
+        if let Some(banks) = &self.banks {
+            if !banks.contains(&bank) {
+                return false;
+            }
+        }
+
+        if let Some(channels) = &self.channels {
+            let Some(channel) = message.channel() else {
+                return false;
+            };
+
+            if !channels.contains(&(channel.index() + 1)) {
+                return false;
+            }
+        }
+
+        if let Some([first, last]) = self.notes {
+            if let NoteOn(_, note, _) | NoteOff(_, note, _) | PolyphonicKeyPressure(_, note, _) =
+                message
+            {
+                if !(first..=last).contains(&u8::from(*note)) {
+                    return false;
+                }
+            }
+        }
+
+        if let Some(messages) = &self.messages {
+            if !messages.contains(&MessageType::of(message)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
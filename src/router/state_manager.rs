@@ -1,24 +1,145 @@
 use anyhow::{Result, format_err};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
 use wmidi::{Channel, Note, Velocity};
 
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct NoteState {
+    pub note: u8,
+    pub state: bool,
+    pub color: u8,
+}
+
 pub struct StateManager {
     states_map: HashMap<u8, Vec<bool>>,
     color_map: HashMap<u8, Vec<u8>>,
-    current_bank: Channel,
+    /// Last value of every control per bank, for knob LED rings and motorized faders
+    control_values: HashMap<u8, HashMap<u8, u8>>,
+    /// Channel index the controller sends every unmapped control on, so feedback reaches it after a restart
+    control_channels: HashMap<u8, u8>,
+    current_bank: u8,
+    changed: bool,
+}
+
+/// Copy of the states that is written to the state file without holding the router lock
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    current_bank: u8,
+    states_map: HashMap<u8, Vec<bool>>,
+    color_map: HashMap<u8, Vec<u8>>,
+    #[serde(default)]
+    control_values: HashMap<u8, HashMap<u8, u8>>,
+    #[serde(default)]
+    control_channels: HashMap<u8, u8>,
+}
+
+impl StateSnapshot {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        // Write to a temporary file first, so a crash mid-write doesn't corrupt the state
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
 }
 
 impl StateManager {
-    pub fn new() -> Self {
+    pub fn new(banks: u8) -> Self {
         Self {
-            states_map: (0..=8)
+            states_map: (0..banks)
                 .map(|i| (i, vec![false; 128]))
                 .collect::<HashMap<_, _>>(),
-            color_map: (0..=8)
+            color_map: (0..banks)
                 .map(|i| (i, vec![0; 128]))
                 .collect::<HashMap<_, _>>(),
-            current_bank: Channel::Ch1, // ToDo: get from controller
+            control_values: HashMap::new(),
+            control_channels: HashMap::new(),
+            current_bank: 0, // ToDo: get from controller
+            changed: false,
+        }
+    }
+
+    /// Adds or removes banks, the states of the remaining banks are kept
+    pub fn set_bank_count(&mut self, banks: u8) {
+        self.states_map.retain(|&bank, _| bank < banks);
+        self.color_map.retain(|&bank, _| bank < banks);
+        self.control_values.retain(|&bank, _| bank < banks);
+
+        for bank in 0..banks {
+            self.states_map
+                .entry(bank)
+                .or_insert_with(|| vec![false; 128]);
+            self.color_map.entry(bank).or_insert_with(|| vec![0; 128]);
+        }
+
+        if self.current_bank >= banks {
+            self.current_bank = 0;
+        }
+
+        self.changed = true;
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let data = fs::read_to_string(path)?;
+        let snapshot: StateSnapshot = serde_json::from_str(&data)?;
+
+        // Only take over banks and notes that still exist
+        for (bank, states) in snapshot.states_map {
+            if let Some(current) = self.states_map.get_mut(&bank) {
+                if current.len() == states.len() {
+                    *current = states;
+                }
+            }
+        }
+
+        for (bank, colors) in snapshot.color_map {
+            if let Some(current) = self.color_map.get_mut(&bank) {
+                if current.len() == colors.len() {
+                    *current = colors;
+                }
+            }
+        }
+
+        self.control_values = snapshot
+            .control_values
+            .into_iter()
+            .filter(|(bank, _)| self.states_map.contains_key(bank))
+            .collect();
+        self.control_channels = snapshot
+            .control_channels
+            .into_iter()
+            .filter(|&(control, channel)| control <= 127 && channel < 16)
+            .collect();
+
+        if self.states_map.contains_key(&snapshot.current_bank) {
+            self.current_bank = snapshot.current_bank;
+        }
+
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Copies the states if they changed since the last snapshot
+    pub fn take_snapshot(&mut self) -> Option<StateSnapshot> {
+        if !self.changed {
+            return None;
         }
+
+        self.changed = false;
+
+        Some(StateSnapshot {
+            current_bank: self.current_bank,
+            states_map: self.states_map.clone(),
+            color_map: self.color_map.clone(),
+            control_values: self.control_values.clone(),
+            control_channels: self.control_channels.clone(),
+        })
+    }
+
+    /// Makes the next snapshot save again, e.g. after a failed write
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
     }
 
     pub fn _get_states_map(&self) -> &HashMap<u8, Vec<bool>> {
@@ -29,71 +150,114 @@ impl StateManager {
         &self.color_map
     }
 
-    pub fn get_current_bank(&self) -> &Channel {
-        &self.current_bank
+    pub fn get_bank_count(&self) -> u8 {
+        self.states_map.len() as u8
+    }
+
+    pub fn get_current_bank(&self) -> u8 {
+        self.current_bank
     }
 
-    pub fn set_current_bank(&mut self, channel: Channel) {
-        self.current_bank = channel;
+    pub fn set_current_bank(&mut self, bank: u8) {
+        self.current_bank = bank;
+        self.changed = true;
     }
 
-    pub fn toggle_note_state(&mut self, bank: &Channel, note: Note) -> Result<()> {
+    pub fn toggle_note_state(&mut self, bank: u8, note: Note) -> Result<()> {
         let states_map = &mut self.states_map;
 
         if let Some(state) = states_map
-            .get_mut(&bank.index())
+            .get_mut(&bank)
             .and_then(|states| states.get_mut(u8::from(note) as usize))
         {
             *state = !*state;
+            self.changed = true;
             return Ok(());
         }
 
         Err(format_err!(""))
     }
 
-    pub fn set_note_state(&mut self, bank: &Channel, note: Note, new_state: bool) -> Result<()> {
+    pub fn set_note_state(&mut self, bank: u8, note: Note, new_state: bool) -> Result<()> {
         let states_map = &mut self.states_map;
 
         if let Some(state) = states_map
-            .get_mut(&bank.index())
+            .get_mut(&bank)
             .and_then(|states| states.get_mut(u8::from(note) as usize))
         {
             *state = new_state;
+            self.changed = true;
             return Ok(());
         }
 
         Err(format_err!(""))
     }
 
-    pub fn set_note_color(
-        &mut self,
-        bank: &Channel,
-        note: Note,
-        new_color: Velocity,
-    ) -> Result<()> {
+    pub fn set_note_color(&mut self, bank: u8, note: Note, new_color: Velocity) -> Result<()> {
         let color_map = &mut self.color_map;
 
         if let Some(color) = color_map
-            .get_mut(&bank.index())
+            .get_mut(&bank)
             .and_then(|colors| colors.get_mut(u8::from(note) as usize))
         {
             *color = u8::from(new_color);
+            self.changed = true;
             return Ok(());
         }
 
         Err(format_err!(""))
     }
 
-    pub fn get_note_state_and_color(&mut self, bank: &Channel, note: Note) -> Result<(&bool, &u8)> {
+    /// Only marks the state as changed if the value differs, controls send a stream of values
+    pub fn set_control_value(&mut self, bank: u8, control: u8, value: u8) {
+        if bank < self.get_bank_count() {
+            let previous = self
+                .control_values
+                .entry(bank)
+                .or_default()
+                .insert(control, value);
+
+            if previous != Some(value) {
+                self.changed = true;
+            }
+        }
+    }
+
+    pub fn set_control_channel(&mut self, control: u8, channel: Channel) {
+        if self.control_channels.insert(control, channel.index()) != Some(channel.index()) {
+            self.changed = true;
+        }
+    }
+
+    pub fn get_control_channel(&self, control: u8) -> Option<Channel> {
+        self.control_channels
+            .get(&control)
+            .and_then(|&channel| Channel::from_index(channel).ok())
+    }
+
+    /// Controls of the bank with a known value
+    pub fn get_control_values(&self, bank: u8) -> Vec<(u8, u8)> {
+        self.control_values
+            .get(&bank)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|(&control, &value)| (control, value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_note_state_and_color(&mut self, bank: u8, note: Note) -> Result<(&bool, &u8)> {
         let states_map = &mut self.states_map;
         let color_map = &mut self.color_map;
 
         if let Some(state) = states_map
-            .get(&bank.index())
+            .get(&bank)
             .and_then(|states| states.get(u8::from(note) as usize))
         {
             if let Some(color) = color_map
-                .get(&bank.index())
+                .get(&bank)
                 .and_then(|colors| colors.get(u8::from(note) as usize))
             {
                 return Ok((state, color));
@@ -103,3 +267,39 @@ impl StateManager {
         Err(format_err!(""))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_control_value_is_not_saved_again() {
+        let mut state_manager = StateManager::new(2);
+
+        state_manager.set_control_value(0, 7, 64);
+        assert!(state_manager.take_snapshot().is_some());
+
+        state_manager.set_control_value(0, 7, 64);
+        assert!(state_manager.take_snapshot().is_none());
+
+        state_manager.set_control_value(0, 7, 65);
+        assert!(state_manager.take_snapshot().is_some());
+    }
+
+    #[test]
+    fn control_channels_survive_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("midi-router-state-{}.json", std::process::id()));
+        let mut state_manager = StateManager::new(2);
+
+        state_manager.set_control_channel(7, Channel::Ch3);
+        state_manager.take_snapshot().unwrap().save(&path).unwrap();
+
+        let mut restarted = StateManager::new(2);
+        restarted.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restarted.get_control_channel(7), Some(Channel::Ch3));
+        assert_eq!(restarted.get_control_channel(8), None);
+    }
+}
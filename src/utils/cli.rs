@@ -0,0 +1,30 @@
+use crate::utils::config::CONFIG_FILE;
+use clap::Parser;
+use log::LevelFilter;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Routes MIDI between a controller and software")]
+pub(crate) struct Cli {
+    /// Path of the config file
+    #[arg(long, default_value = CONFIG_FILE)]
+    pub(crate) config: PathBuf,
+    /// Run without the TUI and log to stdout (or --log-file)
+    #[arg(long)]
+    pub(crate) headless: bool,
+    /// Log file in headless mode
+    #[arg(long, requires = "headless")]
+    pub(crate) log_file: Option<PathBuf>,
+    /// off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = LevelFilter::Info)]
+    pub(crate) log_level: LevelFilter,
+    /// Don't start the REST api
+    #[arg(long)]
+    pub(crate) no_api: bool,
+    /// Development mode, skips the update check
+    #[arg(long)]
+    pub(crate) dev: bool,
+    /// Print all MIDI ports and the ones the config matches, then exit
+    #[arg(long)]
+    pub(crate) list_ports: bool,
+}
@@ -1,7 +1,23 @@
-use crate::router::mapping_config::MappingConfig;
+use crate::{
+    router::{
+        bank_switch::BankSwitchConfig, feedback::FeedbackConfig, led_profile::LedConfig,
+        mapping_config::MappingConfig, port_match::PortMatchConfig, retry::RetryConfig,
+        rule_engine::Rule, software_route::SoftwareRoute,
+    },
+    utils::profile::ControllerProfile,
+};
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use toml::{Table, Value};
+use wmidi::Note;
+
+pub(crate) const CONFIG_FILE: &str = "config.toml";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Config {
@@ -9,16 +25,61 @@ pub(crate) struct Config {
     pub(crate) dev: bool,
     #[serde(rename = "router")]
     pub(crate) router: RouterConfig,
-    #[serde(rename = "maps")]
-    pub(crate) maps: MappingConfig,
     #[serde(rename = "api")]
     pub(crate) api: ApiConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub(crate) struct RouterConfig {
-    pub(crate) controller_name: String,
-    pub(crate) software_name: String,
+    /// From [[software]], or the single software set up with router.software_name
+    pub(crate) software: Vec<SoftwareConfig>,
+    /// Create own to_/from_ software ports instead of using loopback ports (not on Windows)
+    #[serde(default)]
+    pub(crate) virtual_ports: bool,
+    /// How the controller and software names are matched against the port names
+    #[serde(default)]
+    pub(crate) port_match: PortMatchConfig,
+    /// Backoff between connection attempts while the ports are missing
+    #[serde(default)]
+    pub(crate) retry: RetryConfig,
+    /// How toggles are reconciled with the feedback of the software
+    #[serde(default)]
+    pub(crate) feedback: FeedbackConfig,
+    /// From [[controllers]], or the single controller set up in [router] and [maps]
+    pub(crate) controllers: Vec<ControllerConfig>,
+}
+
+/// A controller with its own maps, bank state and LEDs
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct ControllerConfig {
+    /// Port name of the controller
+    pub(crate) name: String,
+    /// Defaults to state.json for the first controller and state-<n>.json for the others
+    #[serde(default)]
+    pub(crate) state_file: PathBuf,
+    #[serde(default)]
+    pub(crate) bank_switch: BankSwitchConfig,
+    #[serde(default)]
+    pub(crate) led: LedConfig,
+    /// Controller profile, relative to the config file
+    pub(crate) profile: Option<PathBuf>,
+    pub(crate) maps: MappingConfig,
+    /// Rules run before the toggle notes and maps, in order
+    #[serde(default)]
+    pub(crate) rules: Vec<Rule>,
+    /// Color palette of the controller profile
+    #[serde(skip)]
+    pub(crate) colors: HashMap<String, u8>,
+}
+
+/// A software target with the routes of the messages it receives
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct SoftwareConfig {
+    /// Name of the to_/from_ software ports
+    pub(crate) name: String,
+    /// Without routes the software receives all messages
+    #[serde(default)]
+    pub(crate) routes: Vec<SoftwareRoute>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,14 +89,335 @@ pub(crate) struct ApiConfig {
     pub(crate) port: u16,
 }
 
+fn default_state_file(index: usize) -> PathBuf {
+    match index {
+        0 => PathBuf::from("state.json"),
+        _ => PathBuf::from(format!("state-{}.json", index + 1)),
+    }
+}
+
+/// Polls the modification time of the config file to detect edits
+pub(crate) struct ConfigWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
 impl Config {
-    pub fn new() -> Result<Self> {
-        if let Ok(data) = fs::read_to_string("config.toml") {
-            let config: Config = toml::from_str(&data)?;
+    pub fn new(path: &Path) -> Result<Self> {
+        if let Ok(data) = fs::read_to_string(path) {
+            let mut table: Table = toml::from_str(&data)?;
+            let mut controllers = take_controllers(&mut table)?;
+            let software = take_software(&mut table)?;
+            let mut profiles = Vec::new();
+
+            for controller in &mut controllers {
+                let profile = load_profile(controller, path)?;
+
+                if let Some(profile) = &profile {
+                    profile.resolve(controller)?;
+                }
+
+                profiles.push(profile);
+            }
+
+            if let Some(Value::Table(router)) = table.get_mut("router") {
+                let controllers = controllers.into_iter().map(Value::Table).collect();
+                router.insert("controllers".to_string(), Value::Array(controllers));
+                router.insert("software".to_string(), Value::Array(software));
+            }
+
+            let mut config: Config = Value::Table(table).try_into()?;
+
+            for (index, (controller, profile)) in config
+                .router
+                .controllers
+                .iter_mut()
+                .zip(profiles)
+                .enumerate()
+            {
+                if let Some(profile) = profile {
+                    controller.colors = profile.colors;
+                }
+
+                if controller.state_file.as_os_str().is_empty() {
+                    controller.state_file = default_state_file(index);
+                }
+
+                // Like profiles, state files don't depend on the working directory
+                let config_dir = path.parent().unwrap_or(Path::new(""));
+                controller.state_file = config_dir.join(&controller.state_file);
+            }
+
+            config.validate()?;
+
+            let router = &mut config.router;
+            let names = router
+                .controllers
+                .iter()
+                .map(|controller| controller.name.as_str())
+                .chain(
+                    router
+                        .software
+                        .iter()
+                        .map(|software| software.name.as_str()),
+                )
+                .collect::<Vec<_>>();
+            router.port_match.compile(&names)?;
 
             Ok(config)
         } else {
-            Err(anyhow!("Could not find config.toml"))
+            Err(anyhow!("Could not find {}", path.display()))
         }
     }
+
+    pub fn validate(&self) -> Result<()> {
+        let controllers = &self.router.controllers;
+
+        if controllers.is_empty() {
+            return Err(anyhow!("At least one controller must be configured"));
+        }
+
+        if cfg!(not(unix)) && self.router.virtual_ports {
+            return Err(anyhow!(
+                "Virtual MIDI ports are not supported on this platform, set virtual_ports = false"
+            ));
+        }
+
+        let software = &self.router.software;
+
+        if software.is_empty() {
+            return Err(anyhow!("At least one software must be configured"));
+        }
+
+        let banks = controllers
+            .iter()
+            .map(|controller| controller.bank_switch.get_banks())
+            .max()
+            .unwrap_or(1);
+
+        for (index, target) in software.iter().enumerate() {
+            if target.name.trim().is_empty() {
+                return Err(anyhow!("Name of software {} must not be empty", index + 1));
+            }
+
+            if software[..index]
+                .iter()
+                .any(|other| other.name == target.name)
+            {
+                return Err(anyhow!("Software {} is configured twice", target.name));
+            }
+
+            for route in &target.routes {
+                route.validate(banks)?;
+            }
+        }
+
+        let software_names = self.router.get_software_names();
+        // Feedback goes to every controller with the note, so each note must have one owner
+        let mut feedback_notes = HashMap::new();
+
+        for (index, controller) in controllers.iter().enumerate() {
+            if controller.name.trim().is_empty() {
+                return Err(anyhow!(
+                    "Name of controller {} must not be empty",
+                    index + 1
+                ));
+            }
+
+            let duplicate = controllers[..index].iter().find(|other| {
+                other.name == controller.name || other.state_file == controller.state_file
+            });
+
+            if let Some(other) = duplicate {
+                return Err(anyhow!(
+                    "Controllers {} and {} use the same name or state file",
+                    other.name,
+                    controller.name
+                ));
+            }
+
+            controller.bank_switch.validate()?;
+            controller.led.validate()?;
+            controller
+                .maps
+                .validate(controller.bank_switch.get_banks())?;
+
+            let maps = MappingConfig::new(controller.maps.clone());
+
+            if let Some(note) = controller
+                .bank_switch
+                .get_switch_notes()
+                .into_iter()
+                .find(|&note| maps.get_note_mode(Note::from_u8_lossy(note)).is_some())
+            {
+                return Err(anyhow!(
+                    "Bank switch note {} of controller {} is also a toggle, momentary or passthrough note",
+                    note,
+                    controller.name
+                ));
+            }
+
+            for (channel, note) in maps.get_feedback_notes(controller.bank_switch.get_banks()) {
+                let owner = feedback_notes
+                    .entry((channel.index(), u8::from(note)))
+                    .or_insert(controller.name.as_str());
+
+                if *owner != controller.name {
+                    return Err(anyhow!(
+                        "Controllers {} and {} both send note {} on channel {}, give their banks different channels or note offsets",
+                        owner,
+                        controller.name,
+                        u8::from(note),
+                        channel.index() + 1
+                    ));
+                }
+            }
+
+            for rule in &controller.rules {
+                rule.validate(controller.bank_switch.get_banks(), &software_names)?;
+            }
+        }
+
+        self.router.port_match.validate(controllers.len())?;
+        self.router.retry.validate()?;
+        self.router.feedback.validate()
+    }
+}
+
+impl RouterConfig {
+    pub fn get_controller_names(&self) -> Vec<&str> {
+        self.controllers
+            .iter()
+            .map(|controller| controller.name.as_str())
+            .collect()
+    }
+
+    pub fn get_software_names(&self) -> Vec<&str> {
+        self.software
+            .iter()
+            .map(|software| software.name.as_str())
+            .collect()
+    }
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            last_modified: modified_time(&path),
+            path,
+        }
+    }
+
+    pub fn has_changed(&mut self) -> bool {
+        let modified = modified_time(&self.path);
+
+        if modified != self.last_modified {
+            self.last_modified = modified;
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Takes the [[controllers]], or builds the only controller from the old single
+/// controller settings in [router], [maps] and [[rules]]
+fn take_controllers(table: &mut Table) -> Result<Vec<Table>> {
+    let legacy_keys = [
+        ("controller_name", "name"),
+        ("state_file", "state_file"),
+        ("bank_switch", "bank_switch"),
+        ("led", "led"),
+        ("profile", "profile"),
+    ];
+
+    if let Some(controllers) = table.remove("controllers") {
+        let has_legacy = table.contains_key("maps")
+            || table.contains_key("rules")
+            || table
+                .get("router")
+                .and_then(Value::as_table)
+                .is_some_and(|router| legacy_keys.iter().any(|(key, _)| router.contains_key(*key)));
+
+        if has_legacy {
+            return Err(anyhow!(
+                "Use either [[controllers]] or router.controller_name with [maps] and [[rules]], not both"
+            ));
+        }
+
+        let Value::Array(controllers) = controllers else {
+            return Err(anyhow!("controllers must be a list of [[controllers]]"));
+        };
+
+        return controllers
+            .into_iter()
+            .map(|controller| match controller {
+                Value::Table(controller) => Ok(controller),
+                _ => Err(anyhow!("controllers must be a list of [[controllers]]")),
+            })
+            .collect();
+    }
+
+    let maps = table.remove("maps");
+    let rules = table.remove("rules");
+    let Some(Value::Table(router)) = table.get_mut("router") else {
+        return Err(anyhow!("Missing [router] section"));
+    };
+
+    let mut controller = Table::new();
+
+    for (key, new_key) in legacy_keys {
+        if let Some(value) = router.remove(key) {
+            controller.insert(new_key.to_string(), value);
+        }
+    }
+
+    if let Some(maps) = maps {
+        controller.insert("maps".to_string(), maps);
+    }
+
+    if let Some(rules) = rules {
+        controller.insert("rules".to_string(), rules);
+    }
+
+    Ok(vec![controller])
+}
+
+/// Takes the [[software]], or builds the only software from router.software_name
+fn take_software(table: &mut Table) -> Result<Vec<Value>> {
+    let software_name = table
+        .get_mut("router")
+        .and_then(Value::as_table_mut)
+        .and_then(|router| router.remove("software_name"));
+
+    match (table.remove("software"), software_name) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "Use either [[software]] or router.software_name, not both"
+        )),
+        (Some(Value::Array(software)), None) => Ok(software),
+        (Some(_), None) => Err(anyhow!("software must be a list of [[software]]")),
+        (None, Some(name)) => {
+            let mut software = Table::new();
+            software.insert("name".to_string(), name);
+            Ok(vec![Value::Table(software)])
+        }
+        (None, None) => Ok(Vec::new()),
+    }
+}
+
+fn load_profile(controller: &Table, config_path: &Path) -> Result<Option<ControllerProfile>> {
+    let Some(path) = controller.get("profile").and_then(Value::as_str) else {
+        return Ok(None);
+    };
+
+    let config_dir = config_path.parent().unwrap_or(Path::new(""));
+    let profile = ControllerProfile::new(&config_dir.join(path))?;
+
+    Ok(Some(profile))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
 }
@@ -1,4 +1,6 @@
+pub(crate) mod cli;
 pub(crate) mod config;
 pub(crate) mod helper;
+pub(crate) mod profile;
 pub(crate) mod threads;
 pub(crate) mod tui;
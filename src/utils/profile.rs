@@ -0,0 +1,157 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+use toml::{Table, Value};
+
+/// Machine-readable description of a controller: pad grid, named buttons and
+/// controls, LED color palette and the default bank switch and LED behaviour
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct ControllerProfile {
+    pub(crate) name: String,
+    grid: Option<PadGrid>,
+    #[serde(default)]
+    buttons: HashMap<String, u8>,
+    #[serde(default)]
+    controls: HashMap<String, u8>,
+    #[serde(default)]
+    pub(crate) colors: HashMap<String, u8>,
+    bank_switch: Option<Table>,
+    led: Option<Table>,
+}
+
+/// Pads are named `<prefix>_<row>_<column>`, both starting at 1
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PadGrid {
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    rows: u8,
+    columns: u8,
+    /// Note of the pad in row 1, column 1
+    first_note: u8,
+    /// Note difference between two rows, negative if row 1 has the highest notes
+    row_step: i16,
+}
+
+fn default_prefix() -> String {
+    "clip".to_string()
+}
+
+impl ControllerProfile {
+    pub fn new(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Could not find profile {}", path.display()))?;
+        let profile: ControllerProfile = toml::from_str(&data)?;
+
+        Ok(profile)
+    }
+
+    pub fn note(&self, name: &str) -> Option<u8> {
+        if let Some(&note) = self.buttons.get(name) {
+            return Some(note);
+        }
+
+        let grid = self.grid.as_ref()?;
+        let (row, column) = name
+            .strip_prefix(&grid.prefix)?
+            .strip_prefix('_')?
+            .split_once('_')?;
+        let (row, column) = (row.parse::<u8>().ok()?, column.parse::<u8>().ok()?);
+
+        if !(1..=grid.rows).contains(&row) || !(1..=grid.columns).contains(&column) {
+            return None;
+        }
+
+        let note = grid.first_note as i16 + (row as i16 - 1) * grid.row_step + (column as i16 - 1);
+
+        u8::try_from(note).ok().filter(|&note| note <= 127)
+    }
+
+    pub fn control(&self, name: &str) -> Option<u8> {
+        self.controls.get(name).copied()
+    }
+
+    pub fn color(&self, name: &str) -> Option<u8> {
+        self.colors.get(name).copied()
+    }
+
+    /// Replaces pad, button, control and color names in a controller config with their
+    /// numbers and fills in the bank switch and LED settings it doesn't set itself
+    pub fn resolve(&self, controller: &mut Table) -> Result<()> {
+        for (key, defaults) in [("bank_switch", &self.bank_switch), ("led", &self.led)] {
+            if let Some(defaults) = defaults {
+                controller
+                    .entry(key)
+                    .or_insert_with(|| Value::Table(defaults.clone()));
+            }
+        }
+
+        if let Some(Value::Table(bank_switch)) = controller.get_mut("bank_switch") {
+            for key in ["notes", "next", "prev"] {
+                self.resolve_value(bank_switch.get_mut(key), |name| self.note(name))?;
+            }
+        }
+
+        if let Some(Value::Table(led)) = controller.get_mut("led") {
+            for key in ["on_velocity", "off_velocity"] {
+                self.resolve_value(led.get_mut(key), |name| self.color(name))?;
+            }
+        }
+
+        if let Some(Value::Table(maps)) = controller.get_mut("maps") {
+            self.resolve_value(maps.get_mut("toggle_notes"), |name| self.note(name))?;
+
+            for key in ["note_modes", "exclusive_groups"] {
+                if let Some(Value::Array(lists)) = maps.get_mut(key) {
+                    for list in lists.iter_mut().filter_map(Value::as_table_mut) {
+                        // `pads = [..]` is an alias for `notes`
+                        if let Some(pads) = list.remove("pads") {
+                            list.insert("notes".to_string(), pads);
+                        }
+
+                        self.resolve_value(list.get_mut("notes"), |name| self.note(name))?;
+                    }
+                }
+            }
+
+            for (key, lookup) in [
+                ("note_map", Self::note as fn(&Self, &str) -> Option<u8>),
+                ("control_map", Self::control),
+            ] {
+                if let Some(Value::Array(maps)) = maps.get_mut(key) {
+                    for map in maps.iter_mut().filter_map(Value::as_table_mut) {
+                        // `pad = "clip_3_5"` is an alias for `note`
+                        if let Some(pad) = map.remove("pad") {
+                            map.insert("note".to_string(), pad);
+                        }
+
+                        self.resolve_value(map.get_mut("note"), |name| lookup(self, name))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_value(
+        &self,
+        value: Option<&mut Value>,
+        lookup: impl Fn(&str) -> Option<u8>,
+    ) -> Result<()> {
+        let values = match value {
+            Some(Value::Array(values)) => values.iter_mut().collect::<Vec<_>>(),
+            Some(value) => vec![value],
+            None => return Ok(()),
+        };
+
+        for value in values {
+            if let Value::String(name) = value {
+                let number = lookup(name)
+                    .ok_or_else(|| anyhow!("Unknown name '{}' in profile {}", name, self.name))?;
+                *value = Value::Integer(number as i64);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,33 +1,55 @@
 use crate::{
-    api::test::test,
-    router::{mapping_config::MappingConfig, midi_connection::MidiRouter},
-    utils::{config::ApiConfig, tui::App},
+    api::{
+        banks::{get_bank_notes, get_current_bank, set_bank_note},
+        config::reload_config,
+        ports::get_ports,
+        status::get_status,
+        test::test,
+    },
+    router::{
+        midi_connection::MidiRouter,
+        router_handle::{RouterHandle, RouterStatus},
+    },
+    utils::{
+        config::{ApiConfig, ConfigWatcher, RouterConfig},
+        tui::App,
+    },
 };
-use actix_web::HttpServer;
+use actix_web::{HttpServer, web};
 use anyhow::Result;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::{
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
         mpsc::Receiver,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::runtime::Runtime;
 
+const PORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// State of the REST api, shown in the TUI
+#[derive(Debug, Clone)]
+pub(crate) enum ApiStatus {
+    Off,
+    Listening(String),
+    Failed(String),
+}
+
 pub(crate) fn tui_thread(
     restart: Arc<AtomicBool>,
     exit: Arc<AtomicBool>,
     log_router: Receiver<String>,
     log_api: Receiver<String>,
-    controller: String,
-    software: String,
+    router: RouterHandle,
+    api_status: Arc<Mutex<ApiStatus>>,
 ) -> Result<()> {
     let mut app = App::new(
-        controller,
-        software,
+        router,
+        api_status,
         exit.clone(),
         restart,
         log_router,
@@ -41,10 +63,7 @@ pub(crate) fn tui_thread(
         res
     });
 
-    let res = handle.join().expect("TUI thread panicked");
-    if let Err(err) = res {
-        return Err(err);
-    }
+    handle.join().expect("TUI thread panicked")?;
 
     exit.store(true, Ordering::SeqCst);
     thread::sleep(Duration::from_millis(200));
@@ -52,27 +71,43 @@ pub(crate) fn tui_thread(
     Ok(())
 }
 
-pub(crate) fn api_thread(exit: Arc<AtomicBool>, config: ApiConfig) {
+pub(crate) fn api_thread(
+    exit: Arc<AtomicBool>,
+    config: ApiConfig,
+    router: RouterHandle,
+    api_status: Arc<Mutex<ApiStatus>>,
+) {
     thread::spawn(move || {
+        let router = web::Data::new(router);
+        let address = format!("{}:{}", config.bind_address, config.port);
+
         let runtime = Runtime::new().unwrap();
         runtime.block_on(async {
-            info!(target: "api",
-                "Starting REST api on {}:{}",
-                config.bind_address, config.port
-            );
+            info!(target: "api", "Starting REST api on {}", address);
 
-            let server = HttpServer::new(|| {
+            let server = match HttpServer::new(move || {
                 actix_web::App::new()
+                    .app_data(router.clone())
                     .wrap(actix_web::middleware::Logger::default().exclude("/health"))
                     .service(test)
+                    .service(get_current_bank)
+                    .service(get_bank_notes)
+                    .service(set_bank_note)
+                    .service(reload_config)
+                    .service(get_status)
+                    .service(get_ports)
             })
             .bind((config.bind_address.clone(), config.port))
-            .expect(&format!(
-                "Failed to bind api to {}:{}",
-                config.bind_address, config.port
-            ))
-            .disable_signals()
-            .run();
+            {
+                Ok(server) => server.disable_signals().run(),
+                Err(err) => {
+                    error!(target: "api", "Failed to bind REST api to {}: {}", address, err);
+                    *api_status.lock().unwrap() = ApiStatus::Failed(err.to_string());
+                    return;
+                }
+            };
+
+            *api_status.lock().unwrap() = ApiStatus::Listening(address);
 
             let handle = server.handle();
 
@@ -85,23 +120,23 @@ pub(crate) fn api_thread(exit: Arc<AtomicBool>, config: ApiConfig) {
                 info!(target: "api", "REST api stopped");
             });
 
-            if let Err(err) = server.await {
-                error!(target: "api", "REST api error: {}", err);
+            match server.await {
+                Ok(_) => *api_status.lock().unwrap() = ApiStatus::Off,
+                Err(err) => {
+                    error!(target: "api", "REST api error: {}", err);
+                    *api_status.lock().unwrap() = ApiStatus::Failed(err.to_string());
+                }
             }
         });
     });
 }
 
-pub(crate) fn router_thread(
-    restart: Arc<AtomicBool>,
-    exit: Arc<AtomicBool>,
-    config: MappingConfig,
-    controller: String,
-    software: String,
-) {
+pub(crate) fn router_thread(restart: Arc<AtomicBool>, exit: Arc<AtomicBool>, router: RouterHandle) {
     thread::spawn(move || {
+        let mut config_watcher = ConfigWatcher::new(router.get_config_path());
+
         while should_continue(&exit) {
-            router_iteration(&restart, &exit, &config, &controller, &software);
+            router_iteration(&restart, &exit, &router, &mut config_watcher);
 
             if should_restart(&restart, &exit) {
                 restart.store(false, Ordering::SeqCst);
@@ -123,19 +158,156 @@ fn should_restart(restart: &Arc<AtomicBool>, exit: &Arc<AtomicBool>) -> bool {
 fn router_iteration(
     restart: &Arc<AtomicBool>,
     exit: &Arc<AtomicBool>,
-    config: &MappingConfig,
-    controller: &str,
-    software: &str,
+    router: &RouterHandle,
+    config_watcher: &mut ConfigWatcher,
 ) {
+    let router_config = router.get_router_config();
+
+    if let RouterStatus::Disconnected = router.get_status() {
+        if !wait_for_ports(restart, exit, router, config_watcher, &router_config) {
+            return;
+        }
+
+        info!("MIDI ports are back, reconnecting...");
+    }
+
     debug!("Starting MIDIRouter...");
-    let mut router = MidiRouter::new(config.clone());
+    router.set_status(RouterStatus::Connecting);
+
+    let retry = &router_config.retry;
+    let mut failed = 0;
+
+    // Kept alive until the end of the iteration, dropping it closes the inputs
+    let midi_router = loop {
+        let mut midi_router = MidiRouter::new(router.clone());
+
+        match MidiRouter::connect(&mut midi_router, &router_config) {
+            Ok(_) => {
+                info!("Started MIDIRouter...");
+                router.set_status(RouterStatus::Connected);
+
+                if let Err(err) = router.refresh_leds() {
+                    error!("Failed to refresh LEDs: {}", err);
+                }
 
-    match MidiRouter::connect(&mut router, controller, software) {
-        Ok(_) => info!("Started MIDIRouter..."),
-        Err(err) => error!("MIDIRouter failed: {}", err),
+                break Some(midi_router);
+            }
+            Err(err) => {
+                failed += 1;
+                drop(midi_router);
+                router.disconnect();
+
+                if !retry.should_retry(failed) {
+                    error!("MIDIRouter failed after {} attempts: {}", failed, err);
+                    router.set_status(RouterStatus::Failed(err.to_string()));
+                    break None;
+                }
+
+                let delay = retry.get_delay(failed);
+                let device = MidiRouter::missing_device(&router_config)
+                    .unwrap_or_else(|| "ports".to_string());
+
+                error!("MIDIRouter failed: {}", err);
+                warn!(
+                    "Waiting for {}, retrying in {:.1}s (attempt {})",
+                    device,
+                    delay.as_secs_f32(),
+                    failed
+                );
+                router.set_status(RouterStatus::Waiting {
+                    device,
+                    attempt: failed,
+                    error: err.to_string(),
+                });
+
+                if !wait(restart, exit, router, config_watcher, delay) {
+                    return;
+                }
+            }
+        }
+    };
+
+    let connected = midi_router.is_some();
+    let mut last_poll = Instant::now();
+
+    while !restart.load(Ordering::SeqCst) && !exit.load(Ordering::SeqCst) {
+        idle(router, config_watcher);
+
+        if connected && last_poll.elapsed() >= PORT_POLL_INTERVAL {
+            last_poll = Instant::now();
+
+            if !MidiRouter::ports_available(&router_config) {
+                warn!("MIDI device disconnected, waiting for it to come back...");
+                router.set_status(RouterStatus::Disconnected);
+                break;
+            }
+        }
+    }
+
+    router.disconnect();
+}
+
+/// Waits for the delay, returns false if the wait was interrupted
+fn wait(
+    restart: &Arc<AtomicBool>,
+    exit: &Arc<AtomicBool>,
+    router: &RouterHandle,
+    config_watcher: &mut ConfigWatcher,
+    delay: Duration,
+) -> bool {
+    let start = Instant::now();
+
+    while start.elapsed() < delay {
+        if restart.load(Ordering::SeqCst) || exit.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        idle(router, config_watcher);
     }
 
+    true
+}
+
+/// Polls the ports after a disconnect, returns false if the wait was interrupted
+fn wait_for_ports(
+    restart: &Arc<AtomicBool>,
+    exit: &Arc<AtomicBool>,
+    router: &RouterHandle,
+    config_watcher: &mut ConfigWatcher,
+    router_config: &RouterConfig,
+) -> bool {
+    let mut last_poll = Instant::now();
+
     while !restart.load(Ordering::SeqCst) && !exit.load(Ordering::SeqCst) {
-        thread::sleep(Duration::from_millis(200));
+        idle(router, config_watcher);
+
+        if last_poll.elapsed() >= PORT_POLL_INTERVAL {
+            last_poll = Instant::now();
+
+            if MidiRouter::ports_available(router_config) {
+                return true;
+            }
+        }
+    }
+
+    // A manual restart connects right away
+    router.set_status(RouterStatus::Connecting);
+    false
+}
+
+fn idle(router: &RouterHandle, config_watcher: &mut ConfigWatcher) {
+    thread::sleep(Duration::from_millis(200));
+    router.save_state();
+
+    if let Err(err) = router.check_feedback() {
+        error!("Failed to check feedback: {}", err);
+    }
+
+    if config_watcher.has_changed() {
+        info!("Config file changed, reloading...");
+
+        if let Err(err) = router.reload_config() {
+            error!("Failed to reload config: {}", err);
+        }
     }
 }
@@ -1,6 +1,10 @@
+use crate::{
+    router::router_handle::{RouterHandle, RouterStatus},
+    utils::threads::ApiStatus,
+};
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
-use log::info;
+use log::{error, info};
 use ratatui::{
     DefaultTerminal, Frame,
     buffer::Buffer,
@@ -12,17 +16,16 @@ use ratatui::{
 };
 use std::{
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
         mpsc::Receiver,
     },
     time::Duration,
 };
 
-#[derive(Debug)]
-pub struct App {
-    controller_name: String,
-    software_name: String,
+pub(crate) struct App {
+    router: RouterHandle,
+    api_status: Arc<Mutex<ApiStatus>>,
     exit: Arc<AtomicBool>,
     restart: Arc<AtomicBool>,
     logs_router: Vec<String>,
@@ -33,16 +36,16 @@ pub struct App {
 
 impl App {
     pub fn new(
-        controller_name: String,
-        software_name: String,
+        router: RouterHandle,
+        api_status: Arc<Mutex<ApiStatus>>,
         exit: Arc<AtomicBool>,
         restart: Arc<AtomicBool>,
         log_rx_router: Receiver<String>,
         log_rx_api: Receiver<String>,
     ) -> Self {
         Self {
-            controller_name,
-            software_name,
+            router,
+            api_status,
             exit,
             restart,
             logs_router: Vec::new(),
@@ -93,8 +96,13 @@ impl App {
         match key_event.code {
             KeyCode::Char('q') => self.exit_now(),
             KeyCode::Char('r') => {
-                self.restart.store(true, Ordering::SeqCst);
                 info!("Restart requested from TUI");
+
+                if let Err(err) = self.router.reload_config() {
+                    error!("Failed to reload config: {}", err);
+                }
+
+                self.restart.store(true, Ordering::SeqCst);
             }
             _ => {}
         }
@@ -113,7 +121,7 @@ impl Widget for &App {
             "Quit ".white(),
             "<Q> ".blue().bold(),
             "| ".green().bold(),
-            "Reload router ".white(),
+            "Reload config & router ".white(),
             "<R>".blue().bold(),
             " ]".bold(),
         ]);
@@ -166,21 +174,87 @@ impl Widget for &App {
             .saturating_sub(api_inner_h as usize)
             .min(u16::MAX as usize) as u16;
 
+        let router_config = self.router.get_router_config();
+        let port_type = if router_config.virtual_ports {
+            " (virtual)"
+        } else {
+            ""
+        };
+
+        let status = match self.router.get_status() {
+            RouterStatus::Connecting => Line::from(vec![
+                "Status:        ".into(),
+                "connecting...".yellow().bold(),
+            ]),
+            RouterStatus::Connected => {
+                Line::from(vec!["Status:        ".into(), "connected".green().bold()])
+            }
+            RouterStatus::Waiting {
+                device,
+                attempt,
+                error,
+            } => Line::from(vec![
+                "Status:        ".into(),
+                format!(
+                    "waiting for {}... (attempt {}) ",
+                    device,
+                    format_attempt(attempt, router_config.retry.get_attempts())
+                )
+                .yellow()
+                .bold(),
+                error.dark_gray(),
+            ]),
+            RouterStatus::Disconnected => Line::from(vec![
+                "Status:        ".into(),
+                "disconnected, waiting for ports...".yellow().bold(),
+            ]),
+            RouterStatus::Failed(err) => Line::from(vec![
+                "Status:        ".into(),
+                "failed: ".red().bold(),
+                err.red(),
+            ]),
+        };
+
+        let api = match self.api_status.lock().unwrap().clone() {
+            ApiStatus::Off => Line::from(vec!["API:           ".into(), "off".dark_gray()]),
+            ApiStatus::Listening(address) => Line::from(vec![
+                "API:           ".into(),
+                "listening on ".green().bold(),
+                address.green().bold(),
+            ]),
+            ApiStatus::Failed(err) => Line::from(vec![
+                "API:           ".into(),
+                "failed: ".red().bold(),
+                err.red(),
+            ]),
+        };
+
+        let software_names = router_config.get_software_names();
+        let port_names = |prefix: &str| {
+            software_names
+                .iter()
+                .map(|name| format!("{}{}", prefix, name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
         let config_text = Text::from(vec![
             Line::from(vec![
                 "Controller:    ".into(),
-                self.controller_name.to_string().red().bold(),
+                router_config.get_controller_names().join(", ").red().bold(),
             ]),
             Line::from(vec![
                 "to Software:   ".into(),
-                "to_".blue().bold(),
-                self.software_name.to_string().blue().bold(),
+                port_names("to_").blue().bold(),
+                port_type.dark_gray(),
             ]),
             Line::from(vec![
                 "from Software: ".into(),
-                "from_".cyan().bold(),
-                self.software_name.to_string().cyan().bold(),
+                port_names("from_").cyan().bold(),
+                port_type.dark_gray(),
             ]),
+            status,
+            api,
         ])
         .white();
 
@@ -214,11 +288,20 @@ impl Widget for &App {
                     .on_black()
                     .dark_gray(),
             )
+            .wrap(Wrap { trim: true })
             .render(bottom[0], buf);
     }
 }
 
-fn format_log_line(s: &str) -> Line {
+fn format_attempt(attempt: u32, attempts: u32) -> String {
+    if attempts == 0 {
+        attempt.to_string()
+    } else {
+        format!("{}/{}", attempt, attempts)
+    }
+}
+
+fn format_log_line(s: &str) -> Line<'_> {
     if let Some(rest) = s.strip_prefix("INFO: ") {
         Line::from(vec!["INFO:  ".green().bold(), rest.white()])
     } else if let Some(rest) = s.strip_prefix("ERROR: ") {